@@ -0,0 +1,101 @@
+// compares the ranking index with loading and sorting every member of a chat,
+// which is how the leaderboard used to be built. Run with `cargo bench`.
+
+use std::{
+    collections::HashSet,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use karmacount::{business::LEADERBOARD_SIZE, db::Store};
+use teloxide::types::{ChatId, UserId};
+
+const MEMBERS: u64 = 10_000;
+const ITERATIONS: u32 = 100;
+const CHAT: ChatId = ChatId(-100);
+
+fn populate(db: &Store) -> anyhow::Result<()> {
+    // karma spread over a few thousand values, with ties
+    let mut seed: u64 = 42;
+    for id in 1..=MEMBERS {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+        let karma = (seed >> 33) as i64 % 5_000 - 1_000;
+        db.add_member(CHAT, UserId(id))?;
+        db.set_karma(UserId(id), karma)?;
+    }
+    Ok(())
+}
+
+// the members of the chat sorted by karma, as before the ranking index
+fn sorted_members(db: &Store) -> anyhow::Result<Vec<(UserId, i64)>> {
+    let members = db.members.get_or(CHAT.to_string(), HashSet::new())?;
+    let mut leaderboard = members
+        .into_iter()
+        .map(|user| Ok((user, db.karma.get_or(user.to_string(), 0)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    leaderboard.sort_by(|(a, karma_a), (b, karma_b)| karma_b.cmp(karma_a).then(a.0.cmp(&b.0)));
+    Ok(leaderboard)
+}
+
+fn bench<T>(name: &str, mut f: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<Duration> {
+    // warm up the page cache
+    black_box(f()?);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f()?);
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    println!("{:<24} {:>12?}", name, elapsed);
+    Ok(elapsed)
+}
+
+fn main() -> anyhow::Result<()> {
+    let db = sled::Config::new().temporary(true).open()?;
+    let store = Store::new(&db)?;
+    populate(&store)?;
+
+    let user = UserId(MEMBERS / 2);
+    let karma = store.karma.get_or(user.to_string(), 0)?;
+
+    println!("{} members, mean of {} runs", MEMBERS, ITERATIONS);
+
+    let old_top = bench("top, sorting members", || {
+        let mut leaderboard = sorted_members(&store)?;
+        leaderboard.truncate(LEADERBOARD_SIZE);
+        Ok(leaderboard)
+    })?;
+    let new_top = bench("top, ranking index", || {
+        store.ranking.top(CHAT, LEADERBOARD_SIZE)
+    })?;
+
+    let old_rank = bench("rank, sorting members", || {
+        let leaderboard = sorted_members(&store)?;
+        Ok(leaderboard.iter().position(|(other, _)| *other == user))
+    })?;
+    let new_rank = bench("rank, ranking index", || {
+        store.ranking.rank(CHAT, user, karma)
+    })?;
+
+    // both approaches must agree
+    assert_eq!(
+        store.ranking.top(CHAT, LEADERBOARD_SIZE)?,
+        sorted_members(&store)?[..LEADERBOARD_SIZE]
+    );
+    assert_eq!(
+        store.ranking.rank(CHAT, user, karma)?,
+        sorted_members(&store)?
+            .iter()
+            .position(|(other, _)| *other == user)
+            .unwrap()
+            + 1
+    );
+
+    println!(
+        "top is {:.0}x faster, rank is {:.0}x faster",
+        old_top.as_secs_f64() / new_top.as_secs_f64(),
+        old_rank.as_secs_f64() / new_rank.as_secs_f64()
+    );
+
+    Ok(())
+}
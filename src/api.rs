@@ -0,0 +1,215 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, UserId};
+
+use crate::{
+    business::LEADERBOARD_SIZE,
+    chart::{self, ChartRange, Format},
+    config::Config,
+    db::{Measure, Store},
+};
+
+// members plotted on the chart of a chat
+const CHART_MEMBERS: usize = 5;
+
+type ApiResult<T> = Result<T, StatusCode>;
+
+fn internal(err: anyhow::Error) -> StatusCode {
+    log::error!("Api error: {}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// the chat whose data the bearer token gives access to
+fn authorize(db: &Store, headers: &HeaderMap) -> ApiResult<ChatId> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    db.api_tokens
+        .get(token)
+        .map_err(internal)?
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
+// users are visible to the tokens of the chats they are members of
+fn authorize_user(db: &Store, headers: &HeaderMap, user: UserId) -> ApiResult<ChatId> {
+    let chat = authorize(db, headers)?;
+    let memberships = db
+        .memberships
+        .get_or(user.to_string(), HashSet::new())
+        .map_err(internal)?;
+
+    match memberships.contains(&chat) {
+        true => Ok(chat),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+fn authorize_chat(db: &Store, headers: &HeaderMap, chat: i64) -> ApiResult<ChatId> {
+    match authorize(db, headers)? {
+        authorized if authorized.0 == chat => Ok(authorized),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    range: Option<String>,
+}
+
+impl RangeQuery {
+    fn range(&self, default: ChartRange) -> ApiResult<ChartRange> {
+        match &self.range {
+            Some(range) => range.parse().map_err(|_| StatusCode::BAD_REQUEST),
+            None => Ok(default),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Member {
+    rank: usize,
+    user: UserId,
+    name: Option<String>,
+    karma: i64,
+}
+
+async fn leaderboard(
+    Extension(db): Extension<Arc<Store>>,
+    Path(chat): Path<i64>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Vec<Member>>> {
+    let chat = authorize_chat(&db, &headers, chat)?;
+
+    let mut members = vec![];
+    for (i, (user, karma)) in db
+        .ranking
+        .top(chat, LEADERBOARD_SIZE)
+        .map_err(internal)?
+        .into_iter()
+        .enumerate()
+    {
+        let profile = db.profiles.get(user.to_string()).map_err(internal)?;
+        members.push(Member {
+            rank: i + 1,
+            user,
+            name: profile.map(|profile| profile.to_string()),
+            karma,
+        });
+    }
+
+    Ok(Json(members))
+}
+
+#[derive(Serialize)]
+struct Karma {
+    user: UserId,
+    name: Option<String>,
+    karma: i64,
+    rank: usize,
+}
+
+async fn karma(
+    Extension(db): Extension<Arc<Store>>,
+    Path(user): Path<u64>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Karma>> {
+    let user = UserId(user);
+    let chat = authorize_user(&db, &headers, user)?;
+
+    let karma = db.karma.get_or(user.to_string(), 0).map_err(internal)?;
+    let rank = db.ranking.rank(chat, user, karma).map_err(internal)?;
+    let profile = db.profiles.get(user.to_string()).map_err(internal)?;
+
+    Ok(Json(Karma {
+        user,
+        name: profile.map(|profile| profile.to_string()),
+        karma,
+        rank,
+    }))
+}
+
+async fn history(
+    Extension(db): Extension<Arc<Store>>,
+    Path(user): Path<u64>,
+    Query(query): Query<RangeQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Vec<Measure>>> {
+    let user = UserId(user);
+    authorize_user(&db, &headers, user)?;
+
+    let range = query.range(ChartRange::All)?;
+    let since = range.since().map(|since| since.timestamp());
+    let history = db
+        .history
+        .since(user, since.unwrap_or(i64::MIN))
+        .map_err(internal)?;
+
+    Ok(Json(history))
+}
+
+async fn chart(
+    Extension(db): Extension<Arc<Store>>,
+    Extension(config): Extension<Arc<Config>>,
+    Path(chat): Path<i64>,
+    Query(query): Query<RangeQuery>,
+    headers: HeaderMap,
+) -> ApiResult<impl IntoResponse> {
+    let chat = authorize_chat(&db, &headers, chat)?;
+
+    let range = query.range(ChartRange::Month)?;
+    let since = range.since().map(|since| since.timestamp());
+    let settings = db
+        .chart_settings
+        .get_or(chat.to_string(), config.defaults.chart_settings())
+        .map_err(internal)?;
+
+    let mut series = vec![];
+    for (user, _) in db.ranking.top(chat, CHART_MEMBERS).map_err(internal)? {
+        let data = db
+            .history
+            .since(user, since.unwrap_or(i64::MIN))
+            .map_err(internal)?;
+        if data.is_empty() {
+            continue;
+        }
+
+        let name = db
+            .profiles
+            .get(user.to_string())
+            .map_err(internal)?
+            .map(|profile| profile.to_string())
+            .unwrap_or_else(|| user.to_string());
+        series.push((name, data));
+    }
+
+    if series.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // rendering takes long enough to stall the other requests on this thread
+    let png =
+        tokio::task::spawn_blocking(move || chart::render(series, range, Format::Png, &settings))
+            .await
+            .map_err(|err| internal(err.into()))?
+            .map_err(internal)?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/chats/:id/leaderboard", get(leaderboard))
+        .route("/chats/:id/chart.png", get(chart))
+        .route("/users/:id/karma", get(karma))
+        .route("/users/:id/history", get(history))
+}
@@ -1,15 +1,313 @@
-use chrono::{Duration, TimeZone, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+
+use crate::db::{Vote, FORGOTTEN};
 
 // this module contains some business logic
 
 pub const DEFAULT_UP: i64 = 6;
 pub const DEFAULT_DOWN: i64 = 2;
-pub const GRAPH_MAX_SIZE: usize = 100;
+pub const HISTORY_RAW_DAYS: i64 = 7;
+pub const HISTORY_HOURLY_DAYS: i64 = 90;
+pub const LEADERBOARD_SIZE: usize = 30;
+pub const TOP_GIVERS: usize = 3;
+pub const HISTORY_PAGE_SIZE: usize = 10;
+pub const MILESTONE: i64 = 100;
+pub const MAX_HOOKS: usize = 5;
+pub const AUDIT_LOG_SIZE: usize = 20;
+pub const CHATS_LIST_SIZE: usize = 50;
+// admins can't add or set karma beyond this, in either direction
+pub const MAX_KARMA: i64 = 1_000_000_000;
 
 pub fn is_assignable_karma_expired(timestamp: i64) -> bool {
     let now = Utc::now();
-    let then = Utc.timestamp(timestamp, 0);
+    let then = Utc.timestamp_opt(timestamp, 0).unwrap();
 
-    let midnight = (then + Duration::days(1)).date().and_hms(0, 0, 0);
+    let midnight = next_midnight(then);
     now.gt(&midnight)
 }
+
+// the multiple of MILESTONE reached going from `before` to `after`, if any
+pub fn milestone(before: i64, after: i64) -> Option<i64> {
+    let reached = after.div_euclid(MILESTONE);
+    match after > before && after > 0 && reached > before.max(0).div_euclid(MILESTONE) {
+        true => Some(reached * MILESTONE),
+        false => None,
+    }
+}
+
+fn next_midnight(time: DateTime<Utc>) -> DateTime<Utc> {
+    (time + Duration::days(1))
+        .date_naive()
+        .and_time(NaiveTime::MIN)
+        .and_utc()
+}
+
+// assignable karma points are restored at this time
+pub fn next_reset() -> DateTime<Utc> {
+    next_midnight(Utc::now())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Karma {
+    Up,
+    Down,
+}
+
+impl Display for Karma {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Karma::Up => write!(f, "+"),
+            Karma::Down => write!(f, "-"),
+        }
+    }
+}
+
+// "+" and "-" votes received and given by a user
+#[derive(Default)]
+pub struct Tally {
+    pub received_up: usize,
+    pub received_down: usize,
+    pub given_up: usize,
+    pub given_down: usize,
+}
+
+impl Tally {
+    pub fn new<'a>(votes: impl IntoIterator<Item = &'a Vote>, user: UserId) -> Self {
+        let mut tally = Tally::default();
+        for vote in votes {
+            let (received, given) = match vote.karma {
+                Karma::Up => (&mut tally.received_up, &mut tally.given_up),
+                Karma::Down => (&mut tally.received_down, &mut tally.given_down),
+            };
+            if vote.receiver == user {
+                *received += 1;
+            }
+            if vote.giver == user {
+                *given += 1;
+            }
+        }
+        tally
+    }
+}
+
+// users that gave `user` the most net karma, best first
+pub fn top_givers(votes: &[Vote], user: UserId) -> Vec<(UserId, i64)> {
+    let mut givers = HashMap::new();
+    for vote in votes.iter().filter(|vote| vote.receiver == user) {
+        let net = givers.entry(vote.giver).or_insert(0);
+        match vote.karma {
+            Karma::Up => *net += 1,
+            Karma::Down => *net -= 1,
+        }
+    }
+
+    let mut givers = givers
+        .into_iter()
+        .filter(|(id, net)| *net > 0 && *id != FORGOTTEN)
+        .collect::<Vec<_>>();
+    givers.sort_by_key(|(id, net)| (-net, id.0));
+    givers.truncate(TOP_GIVERS);
+    givers
+}
+
+// leaderboards of the people casting votes rather than receiving them
+#[derive(Clone, Copy)]
+pub enum Givers {
+    Up,
+    Down,
+    Ratio,
+    Thanked,
+}
+
+impl FromStr for Givers {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "up" => Ok(Givers::Up),
+            "down" => Ok(Givers::Down),
+            "ratio" => Ok(Givers::Ratio),
+            "thanked" => Ok(Givers::Thanked),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Givers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Givers::Up => write!(f, "most + given"),
+            Givers::Down => write!(f, "most - given"),
+            Givers::Ratio => write!(f, "best received/given ratio"),
+            Givers::Thanked => write!(f, "most people thanked"),
+        }
+    }
+}
+
+impl Givers {
+    // scores of the users that gave at least one vote, best first
+    pub fn rank(&self, votes: &[Vote]) -> Vec<(UserId, f64)> {
+        let mut up = HashMap::<UserId, usize>::new();
+        let mut down = HashMap::<UserId, usize>::new();
+        let mut received = HashMap::<UserId, usize>::new();
+        let mut thanked = HashMap::<UserId, HashSet<UserId>>::new();
+
+        for vote in votes {
+            *received.entry(vote.receiver).or_default() += 1;
+            match vote.karma {
+                Karma::Up => {
+                    *up.entry(vote.giver).or_default() += 1;
+                    thanked.entry(vote.giver).or_default().insert(vote.receiver);
+                }
+                Karma::Down => *down.entry(vote.giver).or_default() += 1,
+            }
+        }
+
+        let mut scores = match self {
+            Givers::Up => up
+                .into_iter()
+                .map(|(user, count)| (user, count as f64))
+                .collect::<Vec<_>>(),
+            Givers::Down => down
+                .into_iter()
+                .map(|(user, count)| (user, count as f64))
+                .collect(),
+            Givers::Ratio => {
+                let mut given = up;
+                for (user, count) in down {
+                    *given.entry(user).or_default() += count;
+                }
+                given
+                    .into_iter()
+                    .map(|(user, count)| {
+                        let received = received.get(&user).copied().unwrap_or(0);
+                        (user, received as f64 / count as f64)
+                    })
+                    .collect()
+            }
+            Givers::Thanked => thanked
+                .into_iter()
+                .map(|(user, receivers)| (user, receivers.len() as f64))
+                .collect(),
+        };
+
+        scores.retain(|(user, _)| *user != FORGOTTEN);
+        scores.sort_by(|(a, x), (b, y)| y.total_cmp(x).then(a.0.cmp(&b.0)));
+        scores
+    }
+}
+
+// what a banned user can no longer do in a chat
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BanKind {
+    Give,
+    Receive,
+    Both,
+}
+
+impl FromStr for BanKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "give" => Ok(BanKind::Give),
+            "receive" => Ok(BanKind::Receive),
+            "both" => Ok(BanKind::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for BanKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanKind::Give => write!(f, "giving votes"),
+            BanKind::Receive => write!(f, "receiving votes"),
+            BanKind::Both => write!(f, "giving and receiving votes"),
+        }
+    }
+}
+
+impl BanKind {
+    pub fn covers(&self, other: BanKind) -> bool {
+        *self == BanKind::Both || *self == other
+    }
+}
+
+// karma after `vote`, votes can't take it past MAX_KARMA either
+pub fn apply_vote(karma: i64, vote: &Karma) -> i64 {
+    let karma = match vote {
+        Karma::Up => karma.saturating_add(1),
+        Karma::Down => karma.saturating_sub(1),
+    };
+    karma.clamp(-MAX_KARMA, MAX_KARMA)
+}
+
+// durations like 30m, 12h, 7d or 2w, None if too long to represent
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (index, unit) = s.char_indices().last()?;
+    let amount = s[..index]
+        .parse::<i64>()
+        .ok()
+        .filter(|amount| *amount > 0)?;
+    match unit {
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn votes_stay_within_the_karma_bounds() {
+        assert_eq!(apply_vote(0, &Karma::Up), 1);
+        assert_eq!(apply_vote(0, &Karma::Down), -1);
+        assert_eq!(apply_vote(MAX_KARMA, &Karma::Up), MAX_KARMA);
+        assert_eq!(apply_vote(-MAX_KARMA, &Karma::Down), -MAX_KARMA);
+        assert_eq!(apply_vote(i64::MAX, &Karma::Up), MAX_KARMA);
+        assert_eq!(apply_vote(i64::MIN, &Karma::Down), -MAX_KARMA);
+    }
+
+    #[test]
+    fn durations_have_a_positive_amount_and_a_unit() {
+        assert_eq!(parse_duration("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_duration("12h"), Some(Duration::hours(12)));
+        assert_eq!(parse_duration("7d"), Some(Duration::days(7)));
+        assert_eq!(parse_duration("2w"), Some(Duration::weeks(2)));
+
+        for invalid in ["", "d", "7", "7x", "0d", "-3d", "+-3d"] {
+            assert_eq!(parse_duration(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn multibyte_units_are_refused() {
+        for invalid in ["7é", "🙂", "1🙂", "é"] {
+            assert_eq!(parse_duration(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_refused() {
+        assert_eq!(parse_duration("9223372036854775807w"), None);
+        assert_eq!(parse_duration("100000000000000d"), None);
+
+        // representable, but too far in the future to ban until
+        let duration = parse_duration("100000000d").unwrap();
+        assert_eq!(Utc::now().checked_add_signed(duration), None);
+    }
+}
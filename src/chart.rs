@@ -0,0 +1,866 @@
+use std::{cmp::Ordering, fmt::Display, io::Cursor, ops::RangeInclusive, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use image::{ImageOutputFormat, RgbImage};
+use plotters::{chart::MeshStyle, coord::Shift, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    business::Karma,
+    db::{Measure, Vote},
+};
+
+const MARGIN: i32 = 10;
+const LABEL_AREA: i32 = 40;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const HISTOGRAM_BINS: i64 = 20;
+const CARD_ROW: i32 = 28;
+const CARD_NAME_LENGTH: usize = 24;
+// the columns of the card are laid out for at least this width
+const CARD_MIN_WIDTH: u32 = 600;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const SPARKLINE_WIDTH: usize = 8;
+const SIZES: (RangeInclusive<u32>, RangeInclusive<u32>) = (320..=1920, 240..=1080);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ChartStyle {
+    Line,
+    Step,
+    Area,
+}
+
+impl FromStr for ChartStyle {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(ChartStyle::Line),
+            "step" => Ok(ChartStyle::Step),
+            "area" => Ok(ChartStyle::Area),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ChartStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChartStyle::Line => write!(f, "line"),
+            ChartStyle::Step => write!(f, "step"),
+            ChartStyle::Area => write!(f, "area"),
+        }
+    }
+}
+
+// per chat preferences for every image the bot renders
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChartSettings {
+    pub dark: bool,
+    pub size: (u32, u32),
+    pub style: ChartStyle,
+    pub votes: bool,
+    pub timezone: String,
+}
+
+impl Default for ChartSettings {
+    fn default() -> Self {
+        Self {
+            dark: false,
+            size: (640, 480),
+            style: ChartStyle::Line,
+            votes: false,
+            timezone: "UTC".to_string(),
+        }
+    }
+}
+
+impl Display for ChartSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "- theme {}\n\
+            - size {}x{}\n\
+            - style {}\n\
+            - votes {}\n\
+            - timezone {}",
+            if self.dark { "dark" } else { "light" },
+            self.size.0,
+            self.size.1,
+            self.style,
+            if self.votes { "on" } else { "off" },
+            self.timezone
+        )
+    }
+}
+
+impl ChartSettings {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), &'static str> {
+        match (key, value) {
+            ("theme", "dark") => self.dark = true,
+            ("theme", "light") => self.dark = false,
+            ("theme", _) => return Err("theme must be dark or light"),
+            ("size", value) => {
+                let size = value
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|(w, h)| SIZES.0.contains(w) && SIZES.1.contains(h));
+                self.size = size.ok_or("size must be like 640x480, up to 1920x1080")?;
+            }
+            ("style", value) => {
+                self.style = value
+                    .parse()
+                    .map_err(|_| "style must be line, step or area")?;
+            }
+            ("votes", "on") => self.votes = true,
+            ("votes", "off") => self.votes = false,
+            ("votes", _) => return Err("votes must be on or off"),
+            ("timezone", value) => {
+                let timezone = value
+                    .parse::<Tz>()
+                    .map_err(|_| "unknown timezone, use a name like Europe/Rome")?;
+                self.timezone = timezone.name().to_string();
+            }
+            _ => return Err("unknown setting"),
+        }
+        Ok(())
+    }
+
+    fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    fn theme(&self) -> Theme {
+        let theme = match self.dark {
+            true => Theme::dark(),
+            false => Theme::light(),
+        };
+        Theme {
+            size: self.size,
+            ..theme
+        }
+    }
+}
+
+// colors and size shared by every image the bot renders
+#[derive(Clone, Copy)]
+struct Theme {
+    background: RGBColor,
+    foreground: RGBColor,
+    palette: [RGBColor; 6],
+    up: RGBColor,
+    down: RGBColor,
+    size: (u32, u32),
+}
+
+impl Theme {
+    fn light() -> Self {
+        Self {
+            background: WHITE,
+            foreground: BLACK,
+            palette: [BLUE, RED, GREEN, MAGENTA, CYAN, BLACK],
+            up: RGBColor(0, 150, 0),
+            down: RGBColor(200, 0, 0),
+            size: (640, 480),
+        }
+    }
+
+    fn dark() -> Self {
+        Self {
+            background: RGBColor(32, 33, 36),
+            foreground: RGBColor(220, 220, 220),
+            palette: [
+                RGBColor(100, 160, 255),
+                RGBColor(255, 110, 110),
+                RGBColor(110, 220, 110),
+                RGBColor(230, 130, 230),
+                RGBColor(100, 220, 230),
+                RGBColor(240, 240, 240),
+            ],
+            up: RGBColor(110, 220, 110),
+            down: RGBColor(255, 110, 110),
+            size: (640, 480),
+        }
+    }
+
+    fn accent(&self) -> RGBColor {
+        self.palette[0]
+    }
+
+    fn color(&self, i: usize) -> RGBColor {
+        self.palette[i % self.palette.len()]
+    }
+
+    fn text(&self, size: u32) -> TextStyle<'static> {
+        ("sans-serif", size).into_font().color(&self.foreground)
+    }
+
+    fn style<X, Y, DB>(&self, mesh: &mut MeshStyle<X, Y, DB>)
+    where
+        X: Ranged,
+        Y: Ranged,
+        DB: DrawingBackend,
+    {
+        mesh.axis_style(self.foreground)
+            .bold_line_style(self.foreground.mix(0.2))
+            .light_line_style(self.foreground.mix(0.05))
+            .label_style(self.text(12))
+            .axis_desc_style(self.text(12));
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ChartRange {
+    Week,
+    Month,
+    All,
+}
+
+impl FromStr for ChartRange {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "week" => Ok(ChartRange::Week),
+            "month" => Ok(ChartRange::Month),
+            "all" => Ok(ChartRange::All),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ChartRange {
+    pub fn since(&self) -> Option<DateTime<Utc>> {
+        match self {
+            ChartRange::Week => Some(Utc::now() - Duration::weeks(1)),
+            ChartRange::Month => Some(Utc::now() - Duration::days(30)),
+            ChartRange::All => None,
+        }
+    }
+}
+
+// keeps the measures in range, prepending the last one before it so that each
+// line starts from the karma the user had at the beginning of the range
+fn clip(data: Vec<Measure>, since: i64) -> Vec<Measure> {
+    let start = data.iter().rposition(|m| m.timestamp < since);
+    match start {
+        Some(i) => {
+            let mut clipped = data[i..].to_vec();
+            clipped[0].timestamp = since;
+            clipped
+        }
+        None => data,
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Png,
+    Svg,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(Format::Png),
+            "svg" => Ok(Format::Svg),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Format {
+    pub fn file_name(&self, name: &str) -> String {
+        match self {
+            Format::Png => format!("{}.png", name),
+            Format::Svg => format!("{}.svg", name),
+        }
+    }
+}
+
+pub type Series = (String, Vec<Measure>);
+
+// draws on an in-memory bitmap and encodes it as png
+fn png<F>(size: (u32, u32), draw: F) -> Result<Vec<u8>>
+where
+    F: FnOnce(DrawingArea<BitMapBackend, Shift>) -> Result<()>,
+{
+    let mut buffer = vec![0; (size.0 * size.1 * 3) as usize];
+    draw(BitMapBackend::with_buffer(&mut buffer, size).into_drawing_area())?;
+
+    let image = RgbImage::from_raw(size.0, size.1, buffer)
+        .ok_or_else(|| anyhow!("Invalid image buffer"))?;
+    let mut bytes = Cursor::new(vec![]);
+    image.write_to(&mut bytes, ImageOutputFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+// renders the chart to an encoded image, without touching the filesystem
+pub fn render(
+    series: Vec<Series>,
+    range: ChartRange,
+    format: Format,
+    settings: &ChartSettings,
+) -> Result<Vec<u8>> {
+    match format {
+        Format::Png => png(settings.size, |root| graph(root, series, range, settings)),
+        Format::Svg => {
+            let mut svg = String::new();
+            {
+                let root = SVGBackend::with_string(&mut svg, settings.size).into_drawing_area();
+                graph(root, series, range, settings)?;
+            }
+            Ok(svg.into_bytes())
+        }
+    }
+}
+
+fn graph<DB>(
+    root: DrawingArea<DB, Shift>,
+    series: Vec<Series>,
+    range: ChartRange,
+    settings: &ChartSettings,
+) -> Result<()>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    let theme = settings.theme();
+    let tz = settings.tz();
+
+    root.fill(&theme.background)?;
+    let surface = root.margin(MARGIN + LABEL_AREA, MARGIN, MARGIN, MARGIN + LABEL_AREA);
+
+    let now = Utc::now();
+    let first = series
+        .iter()
+        .filter_map(|(_, data)| data.first())
+        .map(|m| m.timestamp)
+        .min()
+        .unwrap_or_else(|| now.timestamp());
+    let since = range
+        .since()
+        .unwrap_or_else(|| Utc.timestamp_opt(first, 0).unwrap())
+        .min(now - Duration::hours(1));
+
+    // lines are extended up to now with the latest known karma
+    let series = series
+        .into_iter()
+        .map(|(name, data)| (name, trend(data, since.timestamp())))
+        .collect::<Vec<_>>();
+
+    let measures = series.iter().flat_map(|(_, data)| data.iter());
+    let max_karma = measures.clone().map(|m| m.karma).max().unwrap_or(0);
+    let min_karma = measures.map(|m| m.karma).min().unwrap_or(0);
+    // one unit of margin, the ends of the range are drawn on the border
+    let max_karma = max_karma.saturating_add(1);
+    let min_karma = min_karma.saturating_sub(1);
+
+    let mut chart = ChartBuilder::on(&surface)
+        .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA)
+        .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA)
+        .build_cartesian_2d(since..now, min_karma..max_karma)?;
+
+    let mut mesh = chart.configure_mesh();
+    theme.style(&mut mesh);
+    mesh.x_labels(6)
+        .x_label_formatter(&|x| format!("{}", x.with_timezone(&tz).format("%d/%m %H:%M")))
+        .y_desc("karma")
+        .x_desc(format!("time ({})", tz.name()))
+        .draw()?;
+
+    for (i, (name, data)) in series.iter().enumerate() {
+        let color = theme.color(i);
+        let points = data
+            .iter()
+            .map(|m| (Utc.timestamp_opt(m.timestamp, 0).unwrap(), m.karma))
+            .collect::<Vec<_>>();
+
+        // a step keeps the previous karma until the moment of the next vote
+        let line = match settings.style {
+            ChartStyle::Step => points
+                .windows(2)
+                .flat_map(|pair| [pair[0], (pair[1].0, pair[0].1)])
+                .chain(points.last().copied())
+                .collect(),
+            ChartStyle::Line | ChartStyle::Area => points.clone(),
+        };
+
+        let series = match settings.style {
+            ChartStyle::Area => chart.draw_series(
+                AreaSeries::new(line, min_karma, color.mix(0.2)).border_style(color),
+            )?,
+            ChartStyle::Line | ChartStyle::Step => {
+                chart.draw_series(LineSeries::new(line, color))?
+            }
+        };
+        series
+            .label(name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+
+        // every change of karma between two measures is marked as a vote
+        if settings.votes {
+            chart.draw_series(points.windows(2).filter_map(|pair| {
+                let color = match pair[1].1.cmp(&pair[0].1) {
+                    Ordering::Greater => theme.up,
+                    Ordering::Less => theme.down,
+                    Ordering::Equal => return None,
+                };
+                Some(Circle::new(pair[1], 3, color.filled()))
+            }))?;
+        }
+    }
+
+    if series.len() > 1 {
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperLeft)
+            .background_style(theme.background.mix(0.8))
+            .border_style(theme.foreground)
+            .label_font(theme.text(12))
+            .draw()?;
+    }
+
+    root.present()?;
+
+    Ok(())
+}
+
+fn local_day(timestamp: i64, tz: &Tz) -> NaiveDate {
+    Utc.timestamp_opt(timestamp, 0)
+        .unwrap()
+        .with_timezone(tz)
+        .date_naive()
+}
+
+// groups votes by local day, starting from the day of `since`
+fn days(votes: &[Vote], since: i64, now: i64, tz: &Tz) -> Vec<(usize, usize)> {
+    let first = local_day(since, tz);
+    let len = (local_day(now, tz) - first).num_days() as usize + 1;
+    let mut days = vec![(0, 0); len];
+    for vote in votes {
+        let day = (local_day(vote.timestamp, tz) - first).num_days() as usize;
+        if let Some((up, down)) = days.get_mut(day) {
+            match vote.karma {
+                Karma::Up => *up += 1,
+                Karma::Down => *down += 1,
+            }
+        }
+    }
+    days
+}
+
+fn day_label(since: i64, day: usize, tz: &Tz) -> String {
+    let date = local_day(since, tz) + Duration::days(day as i64);
+    date.format("%d/%m").to_string()
+}
+
+pub fn votes_per_day(votes: &[Vote], since: i64, settings: &ChartSettings) -> Result<Vec<u8>> {
+    let theme = settings.theme();
+    let tz = settings.tz();
+    let days = days(votes, since, Utc::now().timestamp(), &tz);
+    let max = days.iter().map(|(up, down)| up + down).max().unwrap_or(0) + 1;
+
+    png(theme.size, |root| {
+        root.fill(&theme.background)?;
+        let surface = root.margin(MARGIN, MARGIN, MARGIN, MARGIN);
+
+        let mut chart = ChartBuilder::on(&surface)
+            .caption("votes per day", theme.text(20))
+            .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA)
+            .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA)
+            .build_cartesian_2d(0..days.len(), 0..max)?;
+
+        let mut mesh = chart.configure_mesh();
+        theme.style(&mut mesh);
+        mesh.disable_x_mesh()
+            .x_labels(8)
+            .x_label_formatter(&|day| day_label(since, *day, &tz))
+            .y_desc("votes")
+            .draw()?;
+
+        chart.draw_series(days.iter().enumerate().map(|(day, (up, down))| {
+            let mut bar = Rectangle::new([(day, 0), (day + 1, up + down)], theme.accent().filled());
+            bar.set_margin(0, 0, 1, 1);
+            bar
+        }))?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+pub fn karma_distribution(karma: &[i64], settings: &ChartSettings) -> Result<Vec<u8>> {
+    let theme = settings.theme();
+    let min = karma.iter().copied().min().unwrap_or(0);
+    let max = karma.iter().copied().max().unwrap_or(0);
+    let width = ((max - min) / HISTOGRAM_BINS + 1).max(1);
+
+    let mut bins = vec![0; ((max - min) / width + 1) as usize];
+    for value in karma {
+        bins[((value - min) / width) as usize] += 1;
+    }
+    let highest = bins.iter().copied().max().unwrap_or(0) + 1;
+
+    png(theme.size, |root| {
+        root.fill(&theme.background)?;
+        let surface = root.margin(MARGIN, MARGIN, MARGIN, MARGIN);
+
+        let mut chart = ChartBuilder::on(&surface)
+            .caption("karma distribution", theme.text(20))
+            .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA)
+            .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA)
+            .build_cartesian_2d(min..(min + width * bins.len() as i64), 0..highest)?;
+
+        let mut mesh = chart.configure_mesh();
+        theme.style(&mut mesh);
+        mesh.disable_x_mesh()
+            .x_desc("karma")
+            .y_desc("members")
+            .draw()?;
+
+        chart.draw_series(bins.iter().enumerate().map(|(i, count)| {
+            let start = min + width * i as i64;
+            Rectangle::new(
+                [(start, 0), (start + width, *count)],
+                theme.accent().filled(),
+            )
+        }))?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+pub fn up_ratio(votes: &[Vote], since: i64, settings: &ChartSettings) -> Result<Vec<u8>> {
+    let theme = settings.theme();
+    let tz = settings.tz();
+    let days = days(votes, since, Utc::now().timestamp(), &tz);
+
+    png(theme.size, |root| {
+        root.fill(&theme.background)?;
+        let surface = root.margin(MARGIN, MARGIN, MARGIN, MARGIN);
+
+        let mut chart = ChartBuilder::on(&surface)
+            .caption("share of + votes per day", theme.text(20))
+            .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA)
+            .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA)
+            .build_cartesian_2d(0..days.len(), 0..101usize)?;
+
+        let mut mesh = chart.configure_mesh();
+        theme.style(&mut mesh);
+        mesh.x_labels(8)
+            .x_label_formatter(&|day| day_label(since, *day, &tz))
+            .y_label_formatter(&|percent| format!("{}%", percent))
+            .draw()?;
+
+        // days without votes have no ratio and are skipped
+        let ratios = days
+            .iter()
+            .enumerate()
+            .filter(|(_, (up, down))| up + down > 0)
+            .map(|(day, (up, down))| (day, up * 100 / (up + down)))
+            .collect::<Vec<_>>();
+
+        chart.draw_series(LineSeries::new(ratios.iter().copied(), theme.accent()))?;
+        chart.draw_series(
+            ratios
+                .iter()
+                .map(|point| Circle::new(*point, 3, theme.accent().filled())),
+        )?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+pub fn activity(votes: &[Vote], settings: &ChartSettings) -> Result<Vec<u8>> {
+    let theme = settings.theme();
+    let tz = settings.tz();
+    let mut grid = [[0usize; 24]; 7];
+    for vote in votes {
+        let time = Utc
+            .timestamp_opt(vote.timestamp, 0)
+            .unwrap()
+            .with_timezone(&tz);
+        let weekday = time.weekday().num_days_from_monday() as usize;
+        grid[weekday][time.hour() as usize] += 1;
+    }
+    let max = grid.iter().flatten().copied().max().unwrap_or(0).max(1);
+
+    png(theme.size, |root| {
+        root.fill(&theme.background)?;
+        let surface = root.margin(MARGIN, MARGIN, MARGIN, MARGIN);
+
+        let mut chart = ChartBuilder::on(&surface)
+            .caption(format!("activity by hour ({})", tz.name()), theme.text(20))
+            .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA)
+            .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA)
+            .build_cartesian_2d(0.0..24.0, -0.5..6.5)?;
+
+        // weekdays are listed top to bottom, with labels centered on each row
+        let mut mesh = chart.configure_mesh();
+        theme.style(&mut mesh);
+        mesh.disable_mesh()
+            .x_labels(13)
+            .x_label_formatter(&|hour| format!("{:.0}", hour))
+            .y_labels(7)
+            .y_label_formatter(&|row: &f64| {
+                let weekday = 6 - row.round() as usize;
+                WEEKDAYS.get(weekday).unwrap_or(&"").to_string()
+            })
+            .x_desc("hour")
+            .draw()?;
+
+        chart.draw_series(grid.iter().enumerate().flat_map(|(weekday, hours)| {
+            hours.iter().enumerate().map(move |(hour, count)| {
+                let (x, y) = (hour as f64, 6.0 - weekday as f64);
+                let color = theme.accent().mix(*count as f64 / max as f64);
+                Rectangle::new([(x, y - 0.5), (x + 1.0, y + 0.5)], color.filled())
+            })
+        }))?;
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+pub struct CardRow {
+    pub rank: usize,
+    pub name: String,
+    pub karma: i64,
+    pub delta: i64,
+    pub trend: Vec<Measure>,
+}
+
+// a table with rank, name, karma, weekly delta and a sparkline of the week
+pub fn leaderboard_card(rows: &[CardRow], settings: &ChartSettings) -> Result<Vec<u8>> {
+    let theme = settings.theme();
+    let width = theme.size.0.max(CARD_MIN_WIDTH);
+    let height = (CARD_ROW * (rows.len() as i32 + 1) + 2 * MARGIN) as u32;
+    let columns = [MARGIN, 50, 330, 410, 490];
+    let sparkline = (width as i32 - MARGIN - columns[4], CARD_ROW - 8);
+
+    png((width, height), |root| {
+        root.fill(&theme.background)?;
+
+        let header = ["#", "name", "karma", "week", "trend"];
+        for (column, text) in columns.iter().zip(header) {
+            root.draw(&Text::new(text, (*column, MARGIN + 6), theme.text(16)))?;
+        }
+
+        let week = Utc::now() - Duration::weeks(1);
+        for (i, row) in rows.iter().enumerate() {
+            let top = MARGIN + CARD_ROW * (i as i32 + 1);
+            if i % 2 == 0 {
+                let stripe = theme.foreground.mix(0.05).filled();
+                root.draw(&Rectangle::new(
+                    [(0, top), (width as i32, top + CARD_ROW)],
+                    stripe,
+                ))?;
+            }
+
+            let name: String = row.name.chars().take(CARD_NAME_LENGTH).collect();
+            let delta = format!("{:+}", row.delta);
+            let delta_color = match row.delta {
+                d if d > 0 => theme.up,
+                d if d < 0 => theme.down,
+                _ => theme.foreground,
+            };
+
+            let y = top + 6;
+            root.draw(&Text::new(
+                row.rank.to_string(),
+                (columns[0], y),
+                theme.text(16),
+            ))?;
+            root.draw(&Text::new(name, (columns[1], y), theme.text(16)))?;
+            root.draw(&Text::new(
+                row.karma.to_string(),
+                (columns[2], y),
+                theme.text(16),
+            ))?;
+            root.draw(&Text::new(
+                delta,
+                (columns[3], y),
+                theme.text(16).color(&delta_color),
+            ))?;
+
+            if row.trend.len() > 1 {
+                let area = root.clone().shrink((columns[4], top + 4), sparkline);
+                let min = row.trend.iter().map(|m| m.karma).min().unwrap_or(0);
+                let max = row.trend.iter().map(|m| m.karma).max().unwrap_or(0);
+                let mut chart = ChartBuilder::on(&area).build_cartesian_2d(
+                    week.timestamp()..Utc::now().timestamp(),
+                    min..max.saturating_add(1),
+                )?;
+                chart.draw_series(LineSeries::new(
+                    row.trend.iter().map(|m| (m.timestamp, m.karma)),
+                    theme.accent().stroke_width(2),
+                ))?;
+            }
+        }
+
+        root.present()?;
+        Ok(())
+    })
+}
+
+// the trend of the last week, extended up to now
+pub fn trend(data: Vec<Measure>, since: i64) -> Vec<Measure> {
+    let mut data = clip(data, since);
+    if let Some(last) = data.last().cloned() {
+        data.push(Measure {
+            timestamp: Utc::now().timestamp(),
+            ..last
+        });
+    }
+    data
+}
+
+pub struct Sparkline {
+    pub line: String,
+    pub min: i64,
+    pub max: i64,
+    pub delta: i64,
+}
+
+impl Display for Sparkline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (min {}, max {}, {:+})",
+            self.line, self.min, self.max, self.delta
+        )
+    }
+}
+
+// samples the karma at evenly spaced moments since `since`, users without
+// measures before it are assumed to have started from zero
+pub fn sparkline(data: Vec<Measure>, since: i64) -> Option<Sparkline> {
+    if data.is_empty() {
+        return None;
+    }
+
+    let data = trend(data, since);
+    let now = Utc::now().timestamp();
+    let step = (now - since) / (SPARKLINE_WIDTH as i64 - 1);
+
+    let samples = (0..SPARKLINE_WIDTH as i64)
+        .map(|i| {
+            let moment = since + step * i;
+            data.iter()
+                .take_while(|m| m.timestamp <= moment)
+                .last()
+                .map(|m| m.karma)
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let min = samples.iter().copied().min()?;
+    let max = samples.iter().copied().max()?;
+    let levels = SPARKS.len() as i64 - 1;
+    let line = samples
+        .iter()
+        .map(|karma| match max - min {
+            0 => SPARKS[0],
+            range => SPARKS[((karma - min) * levels / range) as usize],
+        })
+        .collect();
+
+    Some(Sparkline {
+        line,
+        min,
+        max,
+        delta: samples[samples.len() - 1] - samples[0],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two users with fixed measures, the axis ends at the time of the test
+    fn series() -> Vec<Series> {
+        let start = Utc::now().timestamp() - 3 * 24 * 60 * 60;
+        let measures = |karma: &[i64]| {
+            karma
+                .iter()
+                .enumerate()
+                .map(|(i, karma)| Measure {
+                    timestamp: start + i as i64 * 60 * 60,
+                    karma: *karma,
+                })
+                .collect()
+        };
+        vec![
+            ("alice".to_string(), measures(&[0, 1, 2, 1, 3])),
+            ("bob".to_string(), measures(&[0, -1, -2])),
+        ]
+    }
+
+    #[test]
+    fn svg_has_size_axes_and_legend() {
+        let settings = ChartSettings {
+            size: (800, 600),
+            timezone: "Europe/Rome".to_string(),
+            ..ChartSettings::default()
+        };
+        let svg = render(series(), ChartRange::All, Format::Svg, &settings).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"width="800""#));
+        assert!(svg.contains(r#"height="600""#));
+        // text is written on its own line between the tags
+        for text in ["karma", "time (Europe/Rome)", "alice", "bob"] {
+            assert!(svg.contains(&format!(">\n{}\n</text>", text)), "{}", text);
+        }
+        // a line per user, and its sample in the legend
+        let theme = settings.theme();
+        for i in 0..2 {
+            let RGBColor(r, g, b) = theme.color(i);
+            let stroke = format!(r##"stroke="#{:02X}{:02X}{:02X}""##, r, g, b);
+            let lines = svg
+                .lines()
+                .filter(|line| line.starts_with("<polyline") && line.contains(&stroke));
+            assert_eq!(lines.count(), 2);
+        }
+    }
+
+    #[test]
+    fn card_is_never_narrower_than_its_columns() {
+        let settings = ChartSettings {
+            size: (320, 240),
+            ..ChartSettings::default()
+        };
+        let rows = [CardRow {
+            rank: 1,
+            name: "alice".to_string(),
+            karma: 3,
+            delta: 1,
+            trend: series().remove(0).1,
+        }];
+        let png = leaderboard_card(&rows, &settings).unwrap();
+
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        assert_eq!(width, CARD_MIN_WIDTH);
+    }
+
+    #[test]
+    fn png_has_the_size_of_the_settings() {
+        let settings = ChartSettings {
+            size: (320, 240),
+            ..ChartSettings::default()
+        };
+        let png = render(series(), ChartRange::Week, Format::Png, &settings).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+        assert_eq!((width, height), (320, 240));
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    env, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use teloxide::types::UserId;
+use url::Url;
+
+use crate::{
+    business::{Karma, DEFAULT_DOWN, DEFAULT_UP},
+    chart::ChartSettings,
+    db::Budget,
+    webhook::Webhook,
+};
+
+const DEFAULT_PATH: &str = "config.toml";
+
+// settings are read from the config file, then overridden by the environment
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: String,
+    pub admins: Vec<UserId>,
+    pub database: Database,
+    pub defaults: Defaults,
+    pub log: Log,
+    pub webhook: Option<Webhook>,
+    pub http: Option<Http>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Database {
+    pub path: PathBuf,
+    pub backend: String,
+    // days after which the data of chats that removed the bot is deleted
+    pub purge_after_days: Option<u32>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("data"),
+            backend: "sled".to_string(),
+            purge_after_days: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    pub up: i64,
+    pub down: i64,
+    pub timezone: String,
+    pub triggers: Triggers,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Self {
+            up: DEFAULT_UP,
+            down: DEFAULT_DOWN,
+            timezone: "UTC".to_string(),
+            triggers: Triggers::default(),
+        }
+    }
+}
+
+impl Defaults {
+    // daily points in chats that did not set their own
+    pub fn budget(&self) -> Budget {
+        Budget {
+            up: self.up,
+            down: self.down,
+        }
+    }
+
+    // chart settings of chats that did not set their own
+    pub fn chart_settings(&self) -> ChartSettings {
+        ChartSettings {
+            timezone: self.timezone.clone(),
+            ..ChartSettings::default()
+        }
+    }
+}
+
+// prefixes of the messages that count as votes
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Triggers {
+    pub up: Vec<String>,
+    pub down: Vec<String>,
+}
+
+impl Default for Triggers {
+    fn default() -> Self {
+        Self {
+            up: vec!["+".to_string()],
+            down: vec!["-".to_string()],
+        }
+    }
+}
+
+impl Triggers {
+    // the vote in `text` and whatever follows its trigger
+    pub fn parse<'a>(&self, text: &'a str) -> Option<(Karma, &'a str)> {
+        let find = |triggers: &[String]| {
+            triggers
+                .iter()
+                .find_map(|trigger| text.strip_prefix(trigger.as_str()))
+        };
+
+        find(&self.up)
+            .map(|rest| (Karma::Up, rest))
+            .or_else(|| find(&self.down).map(|rest| (Karma::Down, rest)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub level: String,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+        }
+    }
+}
+
+// embedded http server, for metrics and the api
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Http {
+    pub address: SocketAddr,
+}
+
+fn var<T: FromStr>(name: &str) -> Result<Option<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(name) {
+        Ok(value) => {
+            Ok(Some(value.parse().with_context(|| {
+                format!("{} has an invalid value", name)
+            })?))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    // `path` defaults to $CONFIG, then to config.toml, which may be missing
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var("CONFIG").ok().map(PathBuf::from));
+
+        let mut config = match path {
+            Some(path) => Self::read(&path)?,
+            None if Path::new(DEFAULT_PATH).exists() => Self::read(Path::new(DEFAULT_PATH))?,
+            None => Self::default(),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config in {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(token) = var("TOKEN")? {
+            self.token = token;
+        }
+
+        // ROOT is a comma separated list of admins
+        if let Ok(root) = env::var("ROOT") {
+            self.admins = root
+                .split(',')
+                .map(|id| {
+                    Ok(UserId(
+                        id.trim().parse().context("ROOT has an invalid user id")?,
+                    ))
+                })
+                .collect::<Result<_>>()?;
+        }
+
+        if let Some(path) = var("DB_PATH")? {
+            self.database.path = path;
+        }
+
+        if let Some(days) = var("PURGE_AFTER_DAYS")? {
+            self.database.purge_after_days = Some(days);
+        }
+
+        if let Some(level) = var("RUST_LOG")? {
+            self.log.level = level;
+        }
+
+        // WEBHOOK_URL alone is enough to enable webhook mode
+        if let Some(url) = var::<Url>("WEBHOOK_URL")? {
+            let webhook = self
+                .webhook
+                .get_or_insert_with(|| Webhook::new(url.clone()));
+            webhook.url = url;
+        }
+
+        if let Some(webhook) = &mut self.webhook {
+            if let Some(address) = var("WEBHOOK_ADDRESS")? {
+                webhook.address = address;
+            }
+            if let Some(secret) = var("WEBHOOK_SECRET")? {
+                webhook.secret = Some(secret);
+            }
+            if let Some(cert) = var("WEBHOOK_TLS_CERT")? {
+                webhook.tls_cert = Some(cert);
+            }
+            if let Some(key) = var("WEBHOOK_TLS_KEY")? {
+                webhook.tls_key = Some(key);
+            }
+        }
+
+        if let Some(address) = var("HTTP_ADDRESS")? {
+            self.http = Some(Http { address });
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.token.is_empty() {
+            bail!("token is missing, set it in the config file or with TOKEN");
+        }
+
+        if self.database.backend != "sled" {
+            bail!(
+                "unknown database backend {}, the only one is sled",
+                self.database.backend
+            );
+        }
+
+        if self.database.purge_after_days == Some(0) {
+            bail!("purge_after_days must be at least 1");
+        }
+
+        if self.defaults.up < 0 || self.defaults.down < 0 {
+            bail!("default points can't be negative");
+        }
+
+        Tz::from_str(&self.defaults.timezone)
+            .map_err(|_| anyhow::anyhow!("unknown timezone {}", self.defaults.timezone))?;
+
+        let triggers = &self.defaults.triggers;
+        if triggers.up.is_empty() || triggers.down.is_empty() {
+            bail!("there must be at least one trigger for each vote");
+        }
+
+        if triggers
+            .up
+            .iter()
+            .chain(&triggers.down)
+            .any(String::is_empty)
+        {
+            bail!("triggers can't be empty");
+        }
+
+        // a message must not be able to match both votes
+        for up in &triggers.up {
+            for down in &triggers.down {
+                if up.starts_with(down.as_str()) || down.starts_with(up.as_str()) {
+                    bail!("triggers {} and {} overlap", up, down);
+                }
+            }
+        }
+
+        if let Some(webhook) = &self.webhook {
+            webhook.validate()?;
+        }
+
+        Ok(())
+    }
+}
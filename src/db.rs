@@ -1,11 +1,20 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::Mutex,
+};
 
 use anyhow::Result;
 use bincode::{deserialize, serialize};
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use sled::{Db, Tree};
-use teloxide::types::{MessageId, UserId};
+use sled::{Db, IVec, Tree};
+use teloxide::types::{Chat, ChatId, MessageId, User, UserId};
+
+use crate::{
+    business::{BanKind, Karma, HISTORY_HOURLY_DAYS, HISTORY_RAW_DAYS},
+    chart::ChartSettings,
+};
 
 pub const TREE_KARMA: &str = "karma";
 pub const TREE_UP: &str = "up";
@@ -14,6 +23,36 @@ pub const TREE_LAST: &str = "last";
 pub const TREE_LAST_MESSAGE: &str = "last_message";
 pub const TREE_MEMBERS: &str = "members";
 pub const TREE_GRAPH: &str = "graph";
+pub const TREE_MEMBERSHIPS: &str = "memberships";
+pub const TREE_RANKING: &str = "ranking";
+pub const TREE_PINNED: &str = "pinned";
+pub const TREE_PROFILES: &str = "profiles";
+pub const TREE_HISTORY: &str = "history";
+pub const TREE_VOTES: &str = "votes";
+pub const TREE_USER_VOTES: &str = "user_votes";
+pub const TREE_CHART_SETTINGS: &str = "chart_settings";
+pub const TREE_TIMEZONES: &str = "timezones";
+pub const TREE_API_TOKENS: &str = "api_tokens";
+pub const TREE_HOOKS: &str = "hooks";
+pub const TREE_OUTBOX: &str = "outbox";
+pub const TREE_BUDGETS: &str = "budgets";
+pub const TREE_BANNED: &str = "banned";
+pub const TREE_BANS: &str = "bans";
+pub const TREE_CHAT_SETTINGS: &str = "chat_settings";
+pub const TREE_CHATS: &str = "chats";
+pub const TREE_OPTOUTS: &str = "optouts";
+pub const TREE_INACTIVE: &str = "inactive";
+pub const TREE_DEPARTURES: &str = "departures";
+pub const TREE_AUDIT: &str = "audit";
+
+// stands for the users that asked to be forgotten in the votes of others
+pub const FORGOTTEN: UserId = UserId(0);
+// stands for the target in the action of an audit entry, it is replaced with
+// the name of the target only when the log is shown
+pub const AUDIT_TARGET: &str = "{user}";
+
+// the last activity of a chat is only saved again after this many seconds
+const CHAT_ACTIVITY_RESOLUTION: i64 = 60;
 
 pub struct SpecialTree<T>(Tree, std::marker::PhantomData<T>);
 
@@ -30,6 +69,124 @@ impl Measure {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub username: Option<String>,
+    pub name: String,
+}
+
+impl From<&User> for Profile {
+    fn from(user: &User) -> Self {
+        Self {
+            username: user.username.clone(),
+            name: user.full_name(),
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.username {
+            Some(username) => write!(f, "@{}", username),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Vote {
+    pub chat: ChatId,
+    pub giver: UserId,
+    pub receiver: UserId,
+    pub karma: Karma,
+    pub timestamp: i64,
+    pub message: Option<MessageId>,
+    pub reason: Option<String>,
+}
+
+// daily points of the members of a chat
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Budget {
+    pub up: i64,
+    pub down: i64,
+}
+
+// `until` is when the ban expires, if ever
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ban {
+    pub kind: BanKind,
+    pub until: Option<i64>,
+}
+
+impl Ban {
+    pub fn is_active(&self) -> bool {
+        self.until
+            .map(|until| until > Utc::now().timestamp())
+            .unwrap_or(true)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ChatSettings {
+    // answer the votes blocked by a ban instead of ignoring them
+    pub notify_bans: bool,
+}
+
+// a chat the bot is in, for the registry
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChatInfo {
+    pub title: String,
+    pub last_activity: i64,
+}
+
+// an action of an admin, `target` is the user it was applied to if any
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Audit {
+    pub chat: ChatId,
+    pub admin: UserId,
+    pub target: Option<UserId>,
+    pub action: String,
+    pub timestamp: i64,
+}
+
+impl Audit {
+    pub fn new(chat: ChatId, admin: UserId, target: Option<UserId>, action: String) -> Self {
+        let timestamp = Utc::now().timestamp();
+        Self {
+            chat,
+            admin,
+            target,
+            action,
+            timestamp,
+        }
+    }
+}
+
+// random hex string for tokens and secrets
+pub fn random_token() -> Result<String> {
+    let mut bytes = [0u8; 24];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(hex::encode(bytes))
+}
+
+// http endpoint of a chat that receives its events
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Hook {
+    pub url: String,
+    pub secret: String,
+}
+
+// an event waiting to be delivered to a hook
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Delivery {
+    pub url: String,
+    pub secret: String,
+    pub event: String,
+    pub body: String,
+    pub attempts: u32,
+    pub next_attempt: i64,
+}
+
 impl<T> SpecialTree<T> {
     pub fn get_or<K>(&self, key: K, default: T) -> Result<T>
     where
@@ -80,16 +237,338 @@ impl<T> SpecialTree<T> {
             .transpose()?;
         Ok(value)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<(String, T)>>
+    where
+        T: DeserializeOwned,
+    {
+        self.0.iter().map(|entry| {
+            let (key, bytes) = entry?;
+            let key = String::from_utf8(key.to_vec())?;
+            Ok((key, deserialize(&bytes)?))
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // moves the value of `from`, if any, to `to`
+    pub fn rename<K>(&self, from: K, to: K) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+    {
+        if let Some(bytes) = self.0.remove(from)? {
+            self.0.insert(to, bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+// keys of the ranking tree are (chat, inverted karma, user) so that a prefix
+// scan over a chat yields its members from the highest karma to the lowest.
+pub struct Ranking(Tree);
+
+fn ordered(value: i64) -> [u8; 8] {
+    ((value as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+fn ranking_key(chat: ChatId, karma: i64, user: UserId) -> Vec<u8> {
+    let mut key = Vec::with_capacity(24);
+    key.extend_from_slice(&ordered(chat.0));
+    key.extend_from_slice(&ordered(karma).map(|b| !b));
+    key.extend_from_slice(&user.0.to_be_bytes());
+    key
+}
+
+fn ranking_entry(key: &[u8]) -> Result<(UserId, i64)> {
+    let karma = !u64::from_be_bytes(key[8..16].try_into()?) ^ (1 << 63);
+    let user = u64::from_be_bytes(key[16..24].try_into()?);
+    Ok((UserId(user), karma as i64))
+}
+
+impl Ranking {
+    pub fn insert(&self, chat: ChatId, user: UserId, karma: i64) -> Result<()> {
+        self.0.insert(ranking_key(chat, karma, user), &[])?;
+        Ok(())
+    }
+
+    pub fn remove(&self, chat: ChatId, user: UserId, karma: i64) -> Result<()> {
+        self.0.remove(ranking_key(chat, karma, user))?;
+        Ok(())
+    }
+
+    pub fn top(&self, chat: ChatId, limit: usize) -> Result<Vec<(UserId, i64)>> {
+        self.0
+            .scan_prefix(ordered(chat.0))
+            .take(limit)
+            .map(|entry| ranking_entry(&entry?.0))
+            .collect()
+    }
+
+    // rank is 1-based, users with the same karma are ordered by id
+    pub fn rank(&self, chat: ChatId, user: UserId, karma: i64) -> Result<usize> {
+        let start = ordered(chat.0).to_vec();
+        let end = ranking_key(chat, karma, user);
+        Ok(self.0.range(start..end).count() + 1)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.0.clear()?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// keys of the history tree are (user, timestamp), older entries are
+// periodically downsampled by `History::compact`
+pub struct History(Tree);
+
+fn history_key(user: UserId, timestamp: i64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16);
+    key.extend_from_slice(&user.0.to_be_bytes());
+    key.extend_from_slice(&ordered(timestamp));
+    key
+}
+
+fn history_entry(key: &[u8], value: &[u8]) -> Result<Measure> {
+    let timestamp = u64::from_be_bytes(key[8..16].try_into()?) ^ (1 << 63);
+    Ok(Measure {
+        timestamp: timestamp as i64,
+        karma: deserialize(value)?,
+    })
+}
+
+impl History {
+    pub fn insert(&self, user: UserId, measure: Measure) -> Result<()> {
+        let key = history_key(user, measure.timestamp);
+        self.0.insert(key, serialize(&measure.karma)?)?;
+        Ok(())
+    }
+
+    // returns the measures taken since `timestamp`, preceded by the last one
+    // taken before it if any
+    pub fn since(&self, user: UserId, timestamp: i64) -> Result<Vec<Measure>> {
+        let start = history_key(user, i64::MIN);
+        let split = history_key(user, timestamp);
+        let end = history_key(user, i64::MAX);
+
+        let previous = self.0.range(start..split.clone()).next_back();
+        previous
+            .into_iter()
+            .chain(self.0.range(split..=end))
+            .map(|entry| {
+                let (key, value) = entry?;
+                history_entry(&key, &value)
+            })
+            .collect()
+    }
+
+    pub fn remove_user(&self, user: UserId) -> Result<()> {
+        for key in self.0.scan_prefix(user.0.to_be_bytes()).keys() {
+            self.0.remove(key?)?;
+        }
+        Ok(())
+    }
+
+    // keeps only the last measure of each hour for measures older than
+    // `hourly`, and the last measure of each day for the ones older than
+    // `daily`
+    pub fn compact(&self, user: UserId, hourly: i64, daily: i64) -> Result<()> {
+        let start = history_key(user, i64::MIN);
+        let end = history_key(user, hourly);
+
+        let mut previous: Option<(IVec, i64)> = None;
+        for entry in self.0.range(start..end) {
+            let (key, value) = entry?;
+            let measure = history_entry(&key, &value)?;
+            let bucket = match measure.timestamp < daily {
+                true => measure.timestamp.div_euclid(86400) * 86400,
+                false => measure.timestamp.div_euclid(3600) * 3600,
+            };
+
+            if let Some((previous_key, previous_bucket)) = previous {
+                if previous_bucket == bucket {
+                    self.0.remove(previous_key)?;
+                }
+            }
+            previous = Some((key, bucket));
+        }
+
+        Ok(())
+    }
+}
+
+// the log is keyed by (chat, timestamp, id), so that the votes of a chat are
+// stored in chronological order. The users index, keyed by (user, timestamp,
+// id), points to the log keys of the votes given and received by each user
+pub struct Votes {
+    log: Tree,
+    users: Tree,
+}
+
+fn vote_key(chat: ChatId, timestamp: i64, id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(24);
+    key.extend_from_slice(&ordered(chat.0));
+    key.extend_from_slice(&ordered(timestamp));
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn user_vote_key(user: UserId, timestamp: i64, id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(24);
+    key.extend_from_slice(&user.0.to_be_bytes());
+    key.extend_from_slice(&ordered(timestamp));
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+impl Votes {
+    pub fn insert(&self, id: u64, vote: &Vote) -> Result<()> {
+        let key = vote_key(vote.chat, vote.timestamp, id);
+        self.log.insert(&key, serialize(vote)?)?;
+        self.index(id, vote, &key)
+    }
+
+    fn index(&self, id: u64, vote: &Vote, key: &[u8]) -> Result<()> {
+        for user in [vote.giver, vote.receiver] {
+            self.users
+                .insert(user_vote_key(user, vote.timestamp, id), key)?;
+        }
+        Ok(())
+    }
+
+    pub fn since(&self, chat: ChatId, timestamp: i64) -> Result<Vec<Vote>> {
+        let start = vote_key(chat, timestamp, 0);
+        let end = vote_key(chat, i64::MAX, u64::MAX);
+        self.log
+            .range(start..=end)
+            .map(|entry| Ok(deserialize(&entry?.1)?))
+            .collect()
+    }
+
+    // votes given or received by `user`, optionally only in `chat`, newest first
+    pub fn of(
+        &self,
+        user: UserId,
+        chat: Option<ChatId>,
+        skip: usize,
+        limit: usize,
+    ) -> Result<Vec<Vote>> {
+        let mut votes = vec![];
+        for entry in self.users.scan_prefix(user.0.to_be_bytes()).rev() {
+            if let Some(vote) = self.log.get(entry?.1)? {
+                let vote: Vote = deserialize(&vote)?;
+                if chat.unwrap_or(vote.chat) == vote.chat {
+                    votes.push(vote);
+                }
+            }
+            if votes.len() == skip.saturating_add(limit) {
+                break;
+            }
+        }
+        Ok(votes.into_iter().skip(skip).collect())
+    }
+
+    pub fn remove_chat(&self, chat: ChatId) -> Result<()> {
+        for entry in self.log.scan_prefix(ordered(chat.0)) {
+            let (key, vote) = entry?;
+            let vote: Vote = deserialize(&vote)?;
+            let id = u64::from_be_bytes(key[16..].try_into()?);
+            for user in [vote.giver, vote.receiver] {
+                self.users.remove(user_vote_key(user, vote.timestamp, id))?;
+            }
+            self.log.remove(key)?;
+        }
+        Ok(())
+    }
+
+    pub fn move_chat(&self, from: ChatId, to: ChatId) -> Result<()> {
+        for entry in self.log.scan_prefix(ordered(from.0)) {
+            let (key, vote) = entry?;
+            let mut vote: Vote = deserialize(&vote)?;
+            let id = u64::from_be_bytes(key[16..].try_into()?);
+
+            vote.chat = to;
+            self.log.remove(&key)?;
+            self.insert(id, &vote)?;
+        }
+        Ok(())
+    }
+
+    // replaces `user` with FORGOTTEN in the votes, which other users still see
+    pub fn anonymize(&self, user: UserId) -> Result<()> {
+        for entry in self.users.scan_prefix(user.0.to_be_bytes()) {
+            let (index, key) = entry?;
+            if let Some(vote) = self.log.get(&key)? {
+                let mut vote: Vote = deserialize(&vote)?;
+                if vote.giver == user {
+                    vote.giver = FORGOTTEN;
+                    // the reason was written by the forgotten user
+                    vote.reason = None;
+                }
+                if vote.receiver == user {
+                    vote.receiver = FORGOTTEN;
+                }
+                self.log.insert(&key, serialize(&vote)?)?;
+            }
+            self.users.remove(index)?;
+        }
+        Ok(())
+    }
+
+    pub fn rebuild_index(&self) -> Result<()> {
+        self.users.clear()?;
+        for entry in self.log.iter() {
+            let (key, vote) = entry?;
+            let id = u64::from_be_bytes(key[16..].try_into()?);
+            self.index(id, &deserialize(&vote)?, &key)?;
+        }
+        Ok(())
+    }
+
+    fn is_indexed(&self) -> bool {
+        self.log.is_empty() || !self.users.is_empty()
+    }
 }
 
 pub struct Store {
+    db: Db,
+    // held while karma and the ranking index are updated together, so that
+    // concurrent updates can't leave stale ranking keys behind
+    ranking_lock: Mutex<()>,
     pub karma: SpecialTree<i64>,
     pub up: SpecialTree<i64>,
     pub down: SpecialTree<i64>,
     pub last: SpecialTree<i64>,
     pub last_message: SpecialTree<MessageId>,
     pub members: SpecialTree<HashSet<UserId>>,
-    pub graph: SpecialTree<Vec<Measure>>,
+    pub memberships: SpecialTree<HashSet<ChatId>>,
+    pub ranking: Ranking,
+    pub pinned: SpecialTree<MessageId>,
+    pub profiles: SpecialTree<Profile>,
+    pub history: History,
+    pub votes: Votes,
+    pub chart_settings: SpecialTree<ChartSettings>,
+    pub timezones: SpecialTree<String>,
+    pub api_tokens: SpecialTree<ChatId>,
+    pub hooks: SpecialTree<Vec<Hook>>,
+    pub outbox: SpecialTree<Delivery>,
+    pub budgets: SpecialTree<Budget>,
+    pub bans: SpecialTree<HashMap<UserId, Ban>>,
+    pub chat_settings: SpecialTree<ChatSettings>,
+    pub audit: SpecialTree<Audit>,
+    pub chats: SpecialTree<ChatInfo>,
+    pub optouts: SpecialTree<i64>,
+    pub inactive: SpecialTree<i64>,
+    pub departures: SpecialTree<HashMap<UserId, i64>>,
 }
 
 impl Store {
@@ -100,16 +579,675 @@ impl Store {
         let last = db.open_tree(TREE_LAST)?;
         let last_message = db.open_tree(TREE_LAST_MESSAGE)?;
         let members = db.open_tree(TREE_MEMBERS)?;
-        let graph = db.open_tree(TREE_GRAPH)?;
+        let memberships = db.open_tree(TREE_MEMBERSHIPS)?;
+        let ranking = db.open_tree(TREE_RANKING)?;
+        let pinned = db.open_tree(TREE_PINNED)?;
+        let profiles = db.open_tree(TREE_PROFILES)?;
+        let history = db.open_tree(TREE_HISTORY)?;
+        let votes = db.open_tree(TREE_VOTES)?;
+        let user_votes = db.open_tree(TREE_USER_VOTES)?;
+        let chart_settings = db.open_tree(TREE_CHART_SETTINGS)?;
+        let timezones = db.open_tree(TREE_TIMEZONES)?;
+        let api_tokens = db.open_tree(TREE_API_TOKENS)?;
+        let hooks = db.open_tree(TREE_HOOKS)?;
+        let outbox = db.open_tree(TREE_OUTBOX)?;
+        let budgets = db.open_tree(TREE_BUDGETS)?;
+        let bans = db.open_tree(TREE_BANS)?;
+        let chat_settings = db.open_tree(TREE_CHAT_SETTINGS)?;
+        let audit = db.open_tree(TREE_AUDIT)?;
+        let chats = db.open_tree(TREE_CHATS)?;
+        let optouts = db.open_tree(TREE_OPTOUTS)?;
+        let inactive = db.open_tree(TREE_INACTIVE)?;
+        let departures = db.open_tree(TREE_DEPARTURES)?;
 
-        Ok(Self {
+        let store = Self {
+            db: db.clone(),
+            ranking_lock: Mutex::new(()),
             karma: SpecialTree(karma, std::marker::PhantomData),
             up: SpecialTree(up, std::marker::PhantomData),
             down: SpecialTree(down, std::marker::PhantomData),
             last: SpecialTree(last, std::marker::PhantomData),
             last_message: SpecialTree(last_message, std::marker::PhantomData),
             members: SpecialTree(members, std::marker::PhantomData),
-            graph: SpecialTree(graph, std::marker::PhantomData),
-        })
+            memberships: SpecialTree(memberships, std::marker::PhantomData),
+            ranking: Ranking(ranking),
+            pinned: SpecialTree(pinned, std::marker::PhantomData),
+            profiles: SpecialTree(profiles, std::marker::PhantomData),
+            history: History(history),
+            votes: Votes {
+                log: votes,
+                users: user_votes,
+            },
+            chart_settings: SpecialTree(chart_settings, std::marker::PhantomData),
+            timezones: SpecialTree(timezones, std::marker::PhantomData),
+            api_tokens: SpecialTree(api_tokens, std::marker::PhantomData),
+            hooks: SpecialTree(hooks, std::marker::PhantomData),
+            outbox: SpecialTree(outbox, std::marker::PhantomData),
+            budgets: SpecialTree(budgets, std::marker::PhantomData),
+            bans: SpecialTree(bans, std::marker::PhantomData),
+            chat_settings: SpecialTree(chat_settings, std::marker::PhantomData),
+            audit: SpecialTree(audit, std::marker::PhantomData),
+            chats: SpecialTree(chats, std::marker::PhantomData),
+            optouts: SpecialTree(optouts, std::marker::PhantomData),
+            inactive: SpecialTree(inactive, std::marker::PhantomData),
+            departures: SpecialTree(departures, std::marker::PhantomData),
+        };
+
+        // history used to be a capped list of measures per user
+        if db
+            .tree_names()
+            .iter()
+            .any(|name| name == TREE_GRAPH.as_bytes())
+        {
+            log::info!("Migrating graph to history");
+            let graph: SpecialTree<Vec<Measure>> =
+                SpecialTree(db.open_tree(TREE_GRAPH)?, std::marker::PhantomData);
+            for entry in graph.iter() {
+                let (user, measures) = entry?;
+                let user = UserId(user.parse()?);
+                for measure in measures {
+                    store.history.insert(user, measure)?;
+                }
+            }
+            db.drop_tree(TREE_GRAPH)?;
+        }
+
+        // bans used to only stop users from giving votes, forever
+        if db
+            .tree_names()
+            .iter()
+            .any(|name| name == TREE_BANNED.as_bytes())
+        {
+            log::info!("Migrating banned to bans");
+            let banned: SpecialTree<HashSet<UserId>> =
+                SpecialTree(db.open_tree(TREE_BANNED)?, std::marker::PhantomData);
+            for entry in banned.iter() {
+                let (chat, users) = entry?;
+                let bans = users
+                    .into_iter()
+                    .map(|user| {
+                        let ban = Ban {
+                            kind: BanKind::Give,
+                            until: None,
+                        };
+                        (user, ban)
+                    })
+                    .collect::<HashMap<_, _>>();
+                store.bans.insert(chat, bans)?;
+            }
+            db.drop_tree(TREE_BANNED)?;
+        }
+
+        if store.ranking.is_empty() && !store.members.is_empty() {
+            log::info!("Building ranking index");
+            store.rebuild_ranking()?;
+        }
+
+        if !store.votes.is_indexed() {
+            log::info!("Building votes index");
+            store.votes.rebuild_index()?;
+        }
+
+        Ok(store)
+    }
+
+    pub fn add_member(&self, chat: ChatId, user: UserId) -> Result<()> {
+        if self.is_opted_out(user)? {
+            return Ok(());
+        }
+
+        let _lock = self.ranking_lock.lock().unwrap();
+
+        let mut members = self.members.get_or(chat.to_string(), HashSet::new())?;
+        if !members.insert(user) {
+            return Ok(());
+        }
+        self.members.insert(chat.to_string(), members)?;
+
+        let mut memberships = self.memberships.get_or(user.to_string(), HashSet::new())?;
+        memberships.insert(chat);
+        self.memberships.insert(user.to_string(), memberships)?;
+
+        let mut departures = self.departures.get_or(chat.to_string(), HashMap::new())?;
+        if departures.remove(&user).is_some() {
+            self.departures.insert(chat.to_string(), departures)?;
+        }
+
+        let karma = self.karma.get_or(user.to_string(), 0)?;
+        self.ranking.insert(chat, user, karma)
+    }
+
+    // takes `user` out of the members and the ranking of `chat`, remembering
+    // when they left, returns false if they were not a member
+    pub fn depart(&self, chat: ChatId, user: UserId, timestamp: i64) -> Result<bool> {
+        let _lock = self.ranking_lock.lock().unwrap();
+        let mut members = self.members.get_or(chat.to_string(), HashSet::new())?;
+        if !members.remove(&user) {
+            return Ok(false);
+        }
+        self.members.insert(chat.to_string(), members)?;
+
+        let mut memberships = self.memberships.get_or(user.to_string(), HashSet::new())?;
+        memberships.remove(&chat);
+        self.memberships.insert(user.to_string(), memberships)?;
+
+        let karma = self.karma.get_or(user.to_string(), 0)?;
+        self.ranking.remove(chat, user, karma)?;
+
+        let mut departures = self.departures.get_or(chat.to_string(), HashMap::new())?;
+        departures.insert(user, timestamp);
+        self.departures.insert(chat.to_string(), departures)?;
+
+        Ok(true)
+    }
+
+    // puts a departed user back in `chat` with their karma, returns false if
+    // they had not left it
+    pub fn rejoin(&self, chat: ChatId, user: UserId) -> Result<bool> {
+        let departures = self.departures.get_or(chat.to_string(), HashMap::new())?;
+        if !departures.contains_key(&user) {
+            return Ok(false);
+        }
+
+        self.add_member(chat, user)?;
+        Ok(true)
+    }
+
+    // the best `limit` members of `chat`, with the departed ones if `all`
+    pub fn leaderboard(&self, chat: ChatId, all: bool, limit: usize) -> Result<Vec<(UserId, i64)>> {
+        let mut leaderboard = self.ranking.top(chat, limit)?;
+        if !all {
+            return Ok(leaderboard);
+        }
+
+        let departures = self.departures.get_or(chat.to_string(), HashMap::new())?;
+        for user in departures.into_keys() {
+            leaderboard.push((user, self.karma.get_or(user.to_string(), 0)?));
+        }
+
+        // same order as the ranking
+        leaderboard.sort_by_key(|(user, karma)| (std::cmp::Reverse(*karma), user.0));
+        leaderboard.truncate(limit);
+        Ok(leaderboard)
+    }
+
+    // karma must always be updated through this method to keep the ranking
+    // index of every chat the user is a member of in sync
+    pub fn set_karma(&self, user: UserId, karma: i64) -> Result<()> {
+        let _lock = self.ranking_lock.lock().unwrap();
+        let previous = self.karma.get_or(user.to_string(), 0)?;
+        self.karma.insert(user.to_string(), karma)?;
+        self.history.insert(user, Measure::new(karma))?;
+
+        let memberships = self.memberships.get_or(user.to_string(), HashSet::new())?;
+        for chat in memberships {
+            self.ranking.remove(chat, user, previous)?;
+            self.ranking.insert(chat, user, karma)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn record_vote(&self, vote: Vote) -> Result<()> {
+        let id = self.db.generate_id()?;
+        self.votes.insert(id, &vote)
+    }
+
+    pub fn compact_history(&self) -> Result<()> {
+        let now = Utc::now();
+        let hourly = (now - Duration::days(HISTORY_RAW_DAYS)).timestamp();
+        let daily = (now - Duration::days(HISTORY_HOURLY_DAYS)).timestamp();
+
+        for entry in self.karma.iter() {
+            let (user, _) = entry?;
+            self.history.compact(UserId(user.parse()?), hourly, daily)?;
+        }
+
+        Ok(())
+    }
+
+    // registers `chat` and its last activity
+    pub fn touch_chat(&self, chat: &Chat) -> Result<()> {
+        let title = chat.title().unwrap_or_default().to_string();
+        let now = Utc::now().timestamp();
+        if let Some(info) = self.chats.get(chat.id.to_string())? {
+            if info.title == title && now - info.last_activity < CHAT_ACTIVITY_RESOLUTION {
+                return Ok(());
+            }
+        }
+
+        let info = ChatInfo {
+            title,
+            last_activity: now,
+        };
+        self.chats.insert(chat.id.to_string(), info)
+    }
+
+    // chats in the registry, and those with members from before it existed
+    pub fn known_chats(&self) -> Result<Vec<ChatId>> {
+        let mut chats = HashSet::new();
+        for entry in self
+            .chats
+            .0
+            .iter()
+            .keys()
+            .chain(self.members.0.iter().keys())
+        {
+            chats.insert(ChatId(String::from_utf8(entry?.to_vec())?.parse()?));
+        }
+        Ok(chats.into_iter().collect())
+    }
+
+    // whether anything is stored about `chat`, chats from before the registry
+    // are only known by their members
+    pub fn is_known_chat(&self, chat: ChatId) -> Result<bool> {
+        let key = chat.to_string();
+        Ok(self.chats.get(&key)?.is_some() || self.members.get(&key)?.is_some())
+    }
+
+    // removes everything known about `chat`, except the audit log
+    pub fn purge_chat(&self, chat: ChatId) -> Result<()> {
+        let key = chat.to_string();
+        let _lock = self.ranking_lock.lock().unwrap();
+
+        let members = self.members.remove(&key)?.unwrap_or_default();
+        for user in members {
+            let mut memberships = self.memberships.get_or(user.to_string(), HashSet::new())?;
+            memberships.remove(&chat);
+            self.memberships.insert(user.to_string(), memberships)?;
+
+            let karma = self.karma.get_or(user.to_string(), 0)?;
+            self.ranking.remove(chat, user, karma)?;
+        }
+
+        for entry in self.last_message.0.scan_prefix(format!("{}-", chat)).keys() {
+            self.last_message.0.remove(entry?)?;
+        }
+
+        self.votes.remove_chat(chat)?;
+        self.revoke_api_token(chat)?;
+        self.pinned.remove(&key)?;
+        self.chart_settings.remove(&key)?;
+        self.hooks.remove(&key)?;
+        self.budgets.remove(&key)?;
+        self.bans.remove(&key)?;
+        self.chat_settings.remove(&key)?;
+        self.chats.remove(&key)?;
+        self.inactive.remove(&key)?;
+        self.departures.remove(&key)?;
+
+        Ok(())
+    }
+
+    // moves everything known about `from` to `to`, when a group becomes a
+    // supergroup and gets a new id
+    pub fn move_chat(&self, from: ChatId, to: ChatId) -> Result<()> {
+        let (from_key, to_key) = (from.to_string(), to.to_string());
+        let _lock = self.ranking_lock.lock().unwrap();
+
+        let members = self.members.remove(&from_key)?.unwrap_or_default();
+        let mut moved = self.members.get_or(&to_key, HashSet::new())?;
+        for user in members {
+            let mut memberships = self.memberships.get_or(user.to_string(), HashSet::new())?;
+            memberships.remove(&from);
+            memberships.insert(to);
+            self.memberships.insert(user.to_string(), memberships)?;
+
+            let karma = self.karma.get_or(user.to_string(), 0)?;
+            self.ranking.remove(from, user, karma)?;
+            self.ranking.insert(to, user, karma)?;
+            moved.insert(user);
+        }
+        self.members.insert(&to_key, moved)?;
+
+        // messages of the old group can't be edited or deleted from the new one
+        for entry in self.last_message.0.scan_prefix(format!("{}-", from)).keys() {
+            self.last_message.0.remove(entry?)?;
+        }
+        self.pinned.remove(&from_key)?;
+
+        self.votes.move_chat(from, to)?;
+
+        for entry in self.api_tokens.iter() {
+            let (token, chat) = entry?;
+            if chat == from {
+                self.api_tokens.insert(token, to)?;
+            }
+        }
+
+        for entry in self.audit.iter() {
+            let (key, mut audit) = entry?;
+            if audit.chat == from {
+                audit.chat = to;
+                self.audit.insert(key, audit)?;
+            }
+        }
+
+        self.chart_settings.rename(&from_key, &to_key)?;
+        self.hooks.rename(&from_key, &to_key)?;
+        self.budgets.rename(&from_key, &to_key)?;
+        self.bans.rename(&from_key, &to_key)?;
+        self.chat_settings.rename(&from_key, &to_key)?;
+        self.departures.rename(&from_key, &to_key)?;
+        self.chats.rename(&from_key, &to_key)?;
+        self.inactive.remove(&from_key)?;
+
+        Ok(())
+    }
+
+    // purges the chats that removed the bot before `before`, returns how many
+    pub fn purge_inactive(&self, before: i64) -> Result<usize> {
+        let mut purged = 0;
+        for entry in self.inactive.iter() {
+            let (chat, since) = entry?;
+            if since < before {
+                self.purge_chat(ChatId(chat.parse()?))?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    pub fn is_opted_out(&self, user: UserId) -> Result<bool> {
+        Ok(self.optouts.get(user.to_string())?.is_some())
+    }
+
+    // removes everything known about `user`, except the audit log, and
+    // anonymizes the votes they gave or received
+    pub fn forget_user(&self, user: UserId) -> Result<()> {
+        let key = user.to_string();
+        let _lock = self.ranking_lock.lock().unwrap();
+
+        let karma = self.karma.remove(&key)?.unwrap_or(0);
+        for chat in self.memberships.remove(&key)?.unwrap_or_default() {
+            let mut members = self.members.get_or(chat.to_string(), HashSet::new())?;
+            members.remove(&user);
+            self.members.insert(chat.to_string(), members)?;
+            self.ranking.remove(chat, user, karma)?;
+            self.last_message.remove(format!("{}-{}", chat, user))?;
+        }
+
+        for entry in self.bans.iter() {
+            let (chat, mut bans) = entry?;
+            if bans.remove(&user).is_some() {
+                self.bans.insert(chat, bans)?;
+            }
+        }
+
+        for entry in self.departures.iter() {
+            let (chat, mut departures) = entry?;
+            if departures.remove(&user).is_some() {
+                self.departures.insert(chat, departures)?;
+            }
+        }
+
+        self.up.remove(&key)?;
+        self.down.remove(&key)?;
+        self.last.remove(&key)?;
+        let profile = self.profiles.remove(&key)?;
+        self.timezones.remove(&key)?;
+        self.history.remove_user(user)?;
+        self.votes.anonymize(user)?;
+        self.anonymize_audit(user, profile)?;
+        self.purge_outbox(user)?;
+
+        Ok(())
+    }
+
+    // replaces `user` with FORGOTTEN in the audit log, older actions embed
+    // the name of their target, or its id when it had no profile
+    fn anonymize_audit(&self, user: UserId, profile: Option<Profile>) -> Result<()> {
+        let id = user.to_string();
+        for entry in self.audit.iter() {
+            let (key, mut audit) = entry?;
+            if audit.admin != user && audit.target != Some(user) {
+                continue;
+            }
+
+            if audit.admin == user {
+                audit.admin = FORGOTTEN;
+            }
+            if audit.target == Some(user) {
+                if let Some(profile) = &profile {
+                    audit.action = audit.action.replace(&profile.to_string(), AUDIT_TARGET);
+                }
+                audit.action = audit
+                    .action
+                    .split(' ')
+                    .map(|word| if word == id { AUDIT_TARGET } else { word })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                audit.target = Some(FORGOTTEN);
+            }
+            self.audit.insert(key, audit)?;
+        }
+        Ok(())
+    }
+
+    // events about `user` that were not delivered yet are dropped
+    fn purge_outbox(&self, user: UserId) -> Result<()> {
+        for entry in self.outbox.iter() {
+            let (key, delivery) = entry?;
+            let body: serde_json::Value = serde_json::from_str(&delivery.body)?;
+            if ["giver", "receiver", "user"]
+                .iter()
+                .any(|field| body[field] == user.0)
+            {
+                self.outbox.remove(&key)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_profile(&self, user: &User) -> Result<()> {
+        if self.is_opted_out(user.id)? {
+            return Ok(());
+        }
+
+        self.profiles
+            .insert(user.id.to_string(), Profile::from(user))
+    }
+
+    // usernames are only known for users the bot has already seen
+    pub fn find_username(&self, username: &str) -> Result<Option<UserId>> {
+        let username = username.trim_start_matches('@');
+        for entry in self.profiles.iter() {
+            let (id, profile) = entry?;
+            let matches = profile
+                .username
+                .map(|other| other.eq_ignore_ascii_case(username))
+                .unwrap_or(false);
+            if matches {
+                return Ok(Some(UserId(id.parse()?)));
+            }
+        }
+        Ok(None)
+    }
+
+    // keys are padded so that deliveries are iterated in the order they were queued
+    pub fn enqueue(&self, delivery: Delivery) -> Result<()> {
+        let id = self.db.generate_id()?;
+        self.outbox.insert(format!("{:020}", id), delivery)
+    }
+
+    // whether `user` is banned from `kind` in `chat`, expired bans are removed
+    pub fn is_banned(&self, chat: ChatId, user: UserId, kind: BanKind) -> Result<bool> {
+        let mut bans = self.bans.get_or(chat.to_string(), HashMap::new())?;
+        let ban = match bans.get(&user) {
+            Some(ban) => ban,
+            None => return Ok(false),
+        };
+
+        if !ban.is_active() {
+            bans.remove(&user);
+            self.bans.insert(chat.to_string(), bans)?;
+            return Ok(false);
+        }
+
+        Ok(ban.kind.covers(kind))
+    }
+
+    // gives back all the daily points of `user`
+    pub fn reset_points(&self, user: UserId) -> Result<()> {
+        self.up.remove(user.to_string())?;
+        self.down.remove(user.to_string())?;
+        self.last.remove(user.to_string())?;
+        Ok(())
+    }
+
+    pub fn record_audit(&self, audit: Audit) -> Result<()> {
+        let id = self.db.generate_id()?;
+        self.audit.insert(format!("{:020}", id), audit)
+    }
+
+    // the last actions of the admins of `chat`, or of every chat, newest first
+    pub fn audit_log(&self, chat: Option<ChatId>, limit: usize) -> Result<Vec<Audit>> {
+        let mut log = vec![];
+        for entry in self.audit.0.iter().rev() {
+            let audit: Audit = deserialize(&entry?.1)?;
+            if chat.unwrap_or(audit.chat) == audit.chat {
+                log.push(audit);
+            }
+            if log.len() == limit {
+                break;
+            }
+        }
+        Ok(log)
+    }
+
+    // replaces the api token of `chat` with a new random one
+    pub fn issue_api_token(&self, chat: ChatId) -> Result<String> {
+        self.revoke_api_token(chat)?;
+
+        let token = random_token()?;
+        self.api_tokens.insert(&token, chat)?;
+        Ok(token)
+    }
+
+    pub fn revoke_api_token(&self, chat: ChatId) -> Result<()> {
+        for entry in self.api_tokens.iter() {
+            let (token, other) = entry?;
+            if other == chat {
+                self.api_tokens.remove(token)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn size_on_disk(&self) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn rebuild_ranking(&self) -> Result<()> {
+        let _lock = self.ranking_lock.lock().unwrap();
+        self.ranking.clear()?;
+        self.memberships.clear()?;
+
+        for entry in self.members.iter() {
+            let (chat, members) = entry?;
+            let chat = ChatId(chat.parse()?);
+            for user in members {
+                let mut memberships = self.memberships.get_or(user.to_string(), HashSet::new())?;
+                memberships.insert(chat);
+                self.memberships.insert(user.to_string(), memberships)?;
+
+                let karma = self.karma.get_or(user.to_string(), 0)?;
+                self.ranking.insert(chat, user, karma)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: ChatId = ChatId(-100);
+    const ADMIN: UserId = UserId(1);
+    const USER: UserId = UserId(2);
+
+    fn delivery(body: &str) -> Delivery {
+        Delivery {
+            url: "https://example.org".to_string(),
+            secret: "secret".to_string(),
+            event: "milestone".to_string(),
+            body: body.to_string(),
+            attempts: 0,
+            next_attempt: 0,
+        }
+    }
+
+    #[test]
+    fn purged_chats_are_not_known() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let store = Store::new(&db)?;
+        assert!(!store.is_known_chat(CHAT)?);
+
+        store.add_member(CHAT, USER)?;
+        assert!(store.is_known_chat(CHAT)?);
+
+        store.purge_chat(CHAT)?;
+        assert!(!store.is_known_chat(CHAT)?);
+        Ok(())
+    }
+
+    #[test]
+    fn forgotten_users_leave_no_trace_in_the_audit_log_and_outbox() -> Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let store = Store::new(&db)?;
+        let profile = Profile {
+            username: Some("victim".to_string()),
+            name: "Victim".to_string(),
+        };
+        store.profiles.insert(USER.to_string(), profile)?;
+
+        let audit = |admin, action: &str| Audit::new(CHAT, admin, Some(USER), action.to_string());
+        store.record_audit(audit(ADMIN, "banned {user} from voting"))?;
+        // entries from before the placeholder embed the name or the id
+        store.record_audit(audit(ADMIN, "lifted the ban of @victim"))?;
+        store.record_audit(audit(ADMIN, "set the karma of 2 to 10"))?;
+        store.record_audit(Audit::new(CHAT, USER, None, "reset the budget".to_string()))?;
+
+        store.enqueue(delivery(
+            r#"{"type":"vote_applied","giver":2,"receiver":3}"#,
+        ))?;
+        store.enqueue(delivery(r#"{"type":"milestone","user":2,"karma":100}"#))?;
+        store.enqueue(delivery(r#"{"type":"milestone","user":3,"karma":100}"#))?;
+
+        store.forget_user(USER)?;
+
+        let log = store.audit_log(None, 10)?;
+        let actions = log
+            .iter()
+            .map(|audit| audit.action.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            [
+                "reset the budget",
+                "set the karma of {user} to 10",
+                "lifted the ban of {user}",
+                "banned {user} from voting",
+            ]
+        );
+        assert_eq!(log[0].admin, FORGOTTEN);
+        assert!(log[1..].iter().all(|audit| audit.target == Some(FORGOTTEN)));
+        assert!(log[1..].iter().all(|audit| audit.admin == ADMIN));
+
+        let left = store
+            .outbox
+            .iter()
+            .map(|entry| Ok(entry?.1.body))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(left, [r#"{"type":"milestone","user":3,"karma":100}"#]);
+
+        Ok(())
     }
 }
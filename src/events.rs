@@ -0,0 +1,616 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::redirect::Policy;
+use serde::Serialize;
+use sha2::Sha256;
+use teloxide::types::{ChatId, UserId};
+use tokio::task::JoinSet;
+use url::{Host, Url};
+
+use crate::{
+    business::{self, Karma},
+    db::{Delivery, Store, Vote},
+};
+
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ATTEMPTS: u32 = 10;
+// seconds before the first retry, doubled on each failure up to MAX_BACKOFF
+const BACKOFF: i64 = 10;
+const MAX_BACKOFF: i64 = 60 * 60;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    VoteApplied {
+        chat: ChatId,
+        giver: UserId,
+        receiver: UserId,
+        vote: Karma,
+        karma: i64,
+        reason: Option<String>,
+    },
+    VoteUndone {
+        chat: ChatId,
+        giver: UserId,
+        receiver: UserId,
+        vote: Karma,
+        karma: i64,
+    },
+    Milestone {
+        chat: ChatId,
+        user: UserId,
+        karma: i64,
+    },
+    SeasonEnded {
+        chat: ChatId,
+    },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::VoteApplied { .. } => "vote_applied",
+            Event::VoteUndone { .. } => "vote_undone",
+            Event::Milestone { .. } => "milestone",
+            Event::SeasonEnded { .. } => "season_ended",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    timestamp: i64,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+// queues `event` for every hook of `chat`
+pub fn emit(db: &Store, chat: ChatId, event: Event) -> Result<()> {
+    let hooks = db.hooks.get_or(chat.to_string(), vec![])?;
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let timestamp = Utc::now().timestamp();
+    let body = serde_json::to_string(&Envelope {
+        timestamp,
+        event: &event,
+    })?;
+
+    for hook in hooks {
+        db.enqueue(Delivery {
+            url: hook.url,
+            secret: hook.secret,
+            event: event.name().to_string(),
+            body: body.clone(),
+            attempts: 0,
+            next_attempt: timestamp,
+        })?;
+    }
+
+    Ok(())
+}
+
+// emits the events of `vote`, which took the receiver from `before` to `after`
+pub fn vote_applied(db: &Store, vote: &Vote, before: i64, after: i64) -> Result<()> {
+    emit(
+        db,
+        vote.chat,
+        Event::VoteApplied {
+            chat: vote.chat,
+            giver: vote.giver,
+            receiver: vote.receiver,
+            vote: vote.karma.clone(),
+            karma: after,
+            reason: vote.reason.clone(),
+        },
+    )?;
+
+    if let Some(karma) = business::milestone(before, after) {
+        let user = vote.receiver;
+        emit(
+            db,
+            vote.chat,
+            Event::Milestone {
+                chat: vote.chat,
+                user,
+                karma,
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+// emits the undoing of `vote`, which took the receiver back to `karma`. Votes
+// can't be undone yet, this is called by nothing until they can
+pub fn vote_undone(db: &Store, vote: &Vote, karma: i64) -> Result<()> {
+    emit(
+        db,
+        vote.chat,
+        Event::VoteUndone {
+            chat: vote.chat,
+            giver: vote.giver,
+            receiver: vote.receiver,
+            vote: vote.karma.clone(),
+            karma,
+        },
+    )
+}
+
+// there are no seasons yet, this is called by nothing until there are
+pub fn season_ended(db: &Store, chat: ChatId) -> Result<()> {
+    emit(db, chat, Event::SeasonEnded { chat })
+}
+
+// hex encoded hmac-sha256 of the body, sent as X-Karma-Signature
+fn sign(secret: &str, body: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(body.as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn send(client: &reqwest::Client, delivery: &Delivery) -> Result<()> {
+    let response = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Karma-Event", &delivery.event)
+        .header(
+            "X-Karma-Signature",
+            format!("sha256={}", sign(&delivery.secret, &delivery.body)?),
+        )
+        .body(delivery.body.clone())
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        bail!("status {}", response.status());
+    }
+
+    Ok(())
+}
+
+// hooks may only reach the internet, not the network of the bot
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network" and the shared address space of carriers
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let [first, second, ..] = ip.segments();
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local, link local and documentation
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+                    || (first == 0x2001 && second == 0x0db8))
+            }
+        },
+    }
+}
+
+// the addresses of the host of `url`, fails unless they are all `allowed`
+pub async fn resolve(url: &Url, allowed: fn(IpAddr) -> bool) -> Result<Vec<SocketAddr>> {
+    let port = url.port_or_known_default().context("the url has no port")?;
+    let addresses: Vec<_> = match url.host().context("the url has no host")? {
+        Host::Domain(domain) => tokio::net::lookup_host((domain, port)).await?.collect(),
+        Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+        Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+    };
+
+    if addresses.is_empty() {
+        bail!("the host has no addresses");
+    }
+    if let Some(address) = addresses.iter().find(|address| !allowed(address.ip())) {
+        bail!("the host resolves to {}, which is not public", address.ip());
+    }
+
+    Ok(addresses)
+}
+
+// a client bound to the checked addresses of the host of `url`, so that
+// neither a second lookup nor a redirect can lead somewhere else
+async fn connect(url: &str, allowed: fn(IpAddr) -> bool) -> Result<reqwest::Client> {
+    let url = Url::parse(url)?;
+    let addresses = resolve(&url, allowed).await?;
+
+    let mut builder = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .redirect(Policy::none());
+    if let Some(Host::Domain(domain)) = url.host() {
+        builder = builder.resolve_to_addrs(domain, &addresses);
+    }
+
+    Ok(builder.build()?)
+}
+
+type Attempt = (String, Delivery, Result<()>);
+
+// sends the due deliveries of one url in order, stopping at the first failure
+// so that an endpoint that is down costs a single timeout per tick
+async fn deliver_url(
+    url: String,
+    deliveries: Vec<(String, Delivery)>,
+    allowed: fn(IpAddr) -> bool,
+) -> Vec<Attempt> {
+    let client = match connect(&url, allowed).await {
+        Ok(client) => client,
+        // counts as a failed attempt of the oldest delivery only
+        Err(err) => {
+            return match deliveries.into_iter().next() {
+                Some((key, delivery)) => vec![(key, delivery, Err(err))],
+                None => vec![],
+            }
+        }
+    };
+
+    let mut attempts = vec![];
+    for (key, delivery) in deliveries {
+        let result = send(&client, &delivery).await;
+        let failed = result.is_err();
+        attempts.push((key, delivery, result));
+        if failed {
+            break;
+        }
+    }
+    attempts
+}
+
+fn settle(db: &Store, now: i64, (key, mut delivery, result): Attempt) -> Result<()> {
+    match result {
+        Ok(()) => {
+            db.outbox.remove(&key)?;
+        }
+        Err(err) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+            log::warn!(
+                "Dropping {} event for {}: {}",
+                delivery.event,
+                delivery.url,
+                err
+            );
+            db.outbox.remove(&key)?;
+        }
+        Err(err) => {
+            let backoff = (BACKOFF << delivery.attempts.min(16)).min(MAX_BACKOFF);
+            log::info!(
+                "Retrying {} event for {} in {}s: {}",
+                delivery.event,
+                delivery.url,
+                backoff,
+                err
+            );
+            delivery.attempts += 1;
+            delivery.next_attempt = now + backoff;
+            // unless it was purged meanwhile, with the user it was about
+            if db.outbox.get(&key)?.is_some() {
+                db.outbox.insert(&key, delivery)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// delivers to every url concurrently, so that a slow endpoint can't hold up
+// the others
+async fn deliver(db: &Store, allowed: fn(IpAddr) -> bool) -> Result<()> {
+    let now = Utc::now().timestamp();
+    let mut due: BTreeMap<String, Vec<(String, Delivery)>> = BTreeMap::new();
+    for entry in db.outbox.iter() {
+        let (key, delivery) = entry?;
+        if delivery.next_attempt <= now {
+            due.entry(delivery.url.clone())
+                .or_default()
+                .push((key, delivery));
+        }
+    }
+
+    let mut tasks = JoinSet::new();
+    for (url, deliveries) in due {
+        tasks.spawn(deliver_url(url, deliveries, allowed));
+    }
+
+    while let Some(attempts) = tasks.join_next().await {
+        for attempt in attempts? {
+            settle(db, now, attempt)?;
+        }
+    }
+
+    Ok(())
+}
+
+// sends queued events, the queue is persisted so that events survive restarts
+pub async fn deliverer(db: Arc<Store>) {
+    let mut interval = tokio::time::interval(DELIVERY_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = deliver(&db, is_public).await {
+            log::error!("Could not deliver events: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{Extension, Path},
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+
+    use super::*;
+    use crate::db::Hook;
+
+    const CHAT: ChatId = ChatId(-100);
+
+    // path, event header, signature header and body of each request
+    type Requests = Arc<Mutex<Vec<(String, String, String, String)>>>;
+
+    // answers on /ok with 200 and on /fail with 500
+    fn endpoint() -> (String, Requests) {
+        async fn receive(
+            Path(path): Path<String>,
+            Extension(requests): Extension<Requests>,
+            headers: HeaderMap,
+            body: String,
+        ) -> StatusCode {
+            let header = |name| headers[name].to_str().unwrap().to_string();
+            let ok = path == "ok";
+            requests.lock().unwrap().push((
+                path,
+                header("x-karma-event"),
+                header("x-karma-signature"),
+                body,
+            ));
+            match ok {
+                true => StatusCode::OK,
+                false => StatusCode::INTERNAL_SERVER_ERROR,
+            }
+        }
+
+        let requests = Requests::default();
+        let router = Router::new()
+            .route("/:path", post(receive))
+            .layer(Extension(requests.clone()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        (format!("http://{}", address), requests)
+    }
+
+    fn store(hooks: &[(&str, &str)]) -> Store {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = Store::new(&db).unwrap();
+        let hooks = hooks
+            .iter()
+            .map(|(url, secret)| Hook {
+                url: url.to_string(),
+                secret: secret.to_string(),
+            })
+            .collect();
+        store.hooks.insert(CHAT.to_string(), hooks).unwrap();
+        store
+    }
+
+    fn milestone(db: &Store, karma: i64) {
+        let event = Event::Milestone {
+            chat: CHAT,
+            user: UserId(1),
+            karma,
+        };
+        emit(db, CHAT, event).unwrap();
+    }
+
+    #[test]
+    fn events_are_queued_with_their_payloads() {
+        let db = store(&[("https://example.org/hook", "secret")]);
+        let vote = Vote {
+            chat: CHAT,
+            giver: UserId(1),
+            receiver: UserId(2),
+            karma: Karma::Up,
+            timestamp: 0,
+            message: None,
+            reason: Some("thanks".to_string()),
+        };
+        vote_applied(&db, &vote, 99, 100).unwrap();
+        vote_undone(&db, &vote, 99).unwrap();
+        season_ended(&db, CHAT).unwrap();
+
+        let queued = db
+            .outbox
+            .iter()
+            .map(|entry| {
+                let delivery = entry.unwrap().1;
+                let mut body: serde_json::Value = serde_json::from_str(&delivery.body).unwrap();
+                assert!(body["timestamp"].is_i64());
+                body.as_object_mut().unwrap().remove("timestamp");
+                (delivery.event, body)
+            })
+            .collect::<Vec<_>>();
+
+        let expected = [
+            (
+                "vote_applied",
+                serde_json::json!({"type": "vote_applied", "chat": -100, "giver": 1,
+                    "receiver": 2, "vote": "up", "karma": 100, "reason": "thanks"}),
+            ),
+            (
+                "milestone",
+                serde_json::json!({"type": "milestone", "chat": -100, "user": 2, "karma": 100}),
+            ),
+            (
+                "vote_undone",
+                serde_json::json!({"type": "vote_undone", "chat": -100, "giver": 1,
+                    "receiver": 2, "vote": "up", "karma": 99}),
+            ),
+            (
+                "season_ended",
+                serde_json::json!({"type": "season_ended", "chat": -100}),
+            ),
+        ];
+        assert_eq!(queued.len(), expected.len());
+        for ((event, body), (expected_event, expected_body)) in queued.iter().zip(expected) {
+            assert_eq!(event, expected_event);
+            assert_eq!(body, &expected_body);
+        }
+    }
+
+    #[test]
+    fn signature_is_the_hex_hmac_of_the_body() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog").unwrap(),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn only_public_addresses_are_allowed() {
+        let private = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ];
+        for ip in private {
+            assert!(!is_public(ip.parse().unwrap()), "{} is not public", ip);
+        }
+
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn local_hosts_are_refused() {
+        for url in [
+            "http://127.0.0.1:8080/",
+            "http://[::1]/",
+            "http://localhost/",
+            "https://169.254.169.254/latest/meta-data",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(
+                resolve(&url, is_public).await.is_err(),
+                "{} is refused",
+                url
+            );
+        }
+
+        let url = Url::parse("http://1.1.1.1/").unwrap();
+        assert!(resolve(&url, is_public).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_signed_and_removed() {
+        let (base, requests) = endpoint();
+        let url = format!("{}/ok", base);
+        let db = store(&[(&url, "secret")]);
+        milestone(&db, 100);
+        milestone(&db, 200);
+
+        deliver(&db, |_| true).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for ((_, event, signature, body), karma) in requests.iter().zip([100, 200]) {
+            assert_eq!(event, "milestone");
+            assert_eq!(
+                signature,
+                &format!("sha256={}", sign("secret", body).unwrap())
+            );
+            let body: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(body["type"], "milestone");
+            assert_eq!(body["karma"], karma);
+        }
+        assert!(db.outbox.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_failing_url_holds_up_only_itself() {
+        let (base, requests) = endpoint();
+        let ok = format!("{}/ok", base);
+        let fail = format!("{}/fail", base);
+        let db = store(&[(&fail, "a"), (&ok, "b")]);
+        milestone(&db, 100);
+        milestone(&db, 200);
+
+        let now = Utc::now().timestamp();
+        deliver(&db, |_| true).await.unwrap();
+
+        // the second event for the failing url waits for the next round
+        let paths = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, ..)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(paths.iter().filter(|path| *path == "ok").count(), 2);
+        assert_eq!(paths.iter().filter(|path| *path == "fail").count(), 1);
+
+        let left = db
+            .outbox
+            .iter()
+            .map(|entry| entry.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(left.len(), 2);
+        assert!(left.iter().all(|delivery| delivery.url == fail));
+        assert_eq!(left[0].attempts, 1);
+        assert!(left[0].next_attempt >= now + BACKOFF);
+        assert_eq!(left[1].attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn local_urls_are_not_delivered_to() {
+        let (base, requests) = endpoint();
+        let db = store(&[(&format!("{}/ok", base), "secret")]);
+        milestone(&db, 100);
+
+        deliver(&db, is_public).await.unwrap();
+
+        assert!(requests.lock().unwrap().is_empty());
+        let (_, delivery) = db.outbox.iter().next().unwrap().unwrap();
+        assert_eq!(delivery.attempts, 1);
+    }
+}
@@ -0,0 +1,28 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{extract::Extension, http::StatusCode, routing::get, Router};
+
+use crate::{api, config::Config, db::Store, metrics};
+
+async fn metrics() -> Result<String, StatusCode> {
+    metrics::render().map_err(|err| {
+        log::error!("Could not render metrics: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+pub async fn serve(address: SocketAddr, db: Arc<Store>, config: Arc<Config>) -> Result<()> {
+    let router = Router::new()
+        .route("/metrics", get(metrics))
+        .merge(api::router())
+        .layer(Extension(db))
+        .layer(Extension(config));
+
+    log::info!("Serving http on {}", address);
+    axum::Server::try_bind(&address)?
+        .serve(router.into_make_service())
+        .await?;
+
+    Ok(())
+}
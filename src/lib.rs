@@ -0,0 +1,10 @@
+pub mod api;
+pub mod business;
+pub mod chart;
+pub mod config;
+pub mod db;
+pub mod events;
+pub mod http;
+pub mod metrics;
+pub mod telegram;
+pub mod webhook;
@@ -1,26 +1,59 @@
-mod business;
-mod db;
-mod telegram;
-
-use std::{env, sync::Arc};
+use std::{env, path::PathBuf, process, sync::Arc, time::Duration};
 
+use chrono::Utc;
+use karmacount::{
+    config::Config,
+    db, events, http, metrics,
+    telegram::{
+        admin_command, group_command, lifecycle, message, pinned, roles, root_command, user_command,
+    },
+    webhook,
+};
 use teloxide::{prelude::*, types::ParseMode};
 
-use crate::telegram::{group_command, message, root_command, user_command};
-
-const DB_PATH: &str = "data";
+const HISTORY_COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    pretty_env_logger::init();
+    let mut path = None;
+    let mut check = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => path = args.next().map(PathBuf::from),
+            "--check-config" => check = true,
+            _ => {
+                eprintln!("usage: karmacount [--config <path>] [--check-config]");
+                process::exit(2);
+            }
+        }
+    }
+
+    let config = match Config::load(path.as_deref()) {
+        Ok(config) => Arc::new(config),
+        Err(err) => {
+            eprintln!("Invalid configuration: {:#}", err);
+            process::exit(1);
+        }
+    };
+
+    if check {
+        println!("Configuration is valid.");
+        return Ok(());
+    }
+
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&config.log.level)
+        .init();
     log::info!("Starting karma bot...");
 
-    let root = env::var("ROOT").map_or(UserId(0), |root| UserId(root.parse::<u64>().unwrap_or(0)));
-    let token = env::var("TOKEN").expect("TOKEN must be set");
+    if config.admins.is_empty() {
+        log::warn!("No admins configured, admin commands are disabled");
+    }
 
-    let bot = Bot::new(token).parse_mode(ParseMode::Html);
+    let bot = Bot::new(&config.token).parse_mode(ParseMode::Html);
 
-    let db = sled::open(DB_PATH)?;
+    let db = sled::open(&config.database.path)?;
     if db.was_recovered() {
         log::info!("Database was recovered");
     } else {
@@ -28,14 +61,69 @@ async fn main() -> anyhow::Result<()> {
     }
 
     let store = Arc::new(db::Store::new(&db)?);
+    let pinned = Arc::new(pinned::Pinned::new());
+    let roles = Arc::new(roles::Roles::new());
+
+    let compacted = store.clone();
+    let purge_after = config.database.purge_after_days;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HISTORY_COMPACTION_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = compacted.compact_history() {
+                log::error!("Could not compact history: {}", err);
+            }
+
+            // data of chats that removed the bot long ago
+            if let Some(days) = purge_after {
+                let before = (Utc::now() - chrono::Duration::days(days.into())).timestamp();
+                match compacted.purge_inactive(before) {
+                    Ok(0) => {}
+                    Ok(purged) => log::info!("Purged {} inactive chats", purged),
+                    Err(err) => log::error!("Could not purge inactive chats: {}", err),
+                }
+            }
+        }
+    });
+
+    tokio::spawn(pinned::updater(bot.clone(), store.clone(), pinned.clone()));
+    tokio::spawn(events::deliverer(store.clone()));
+    tokio::spawn(lifecycle::reconciler(bot.clone(), store.clone()));
+
+    if let Some(http) = &config.http {
+        let address = http.address;
+        let served = store.clone();
+        let config = config.clone();
+        tokio::spawn(metrics::collector(store.clone()));
+        tokio::spawn(async move {
+            if let Err(err) = http::serve(address, served, config).await {
+                log::error!("Http server error: {}", err);
+            }
+        });
+    }
 
     let handler = dptree::entry()
         .branch(Update::filter_callback_query().endpoint(message::callback_handler))
+        .branch(Update::filter_my_chat_member().endpoint(lifecycle::my_chat_member_handler))
+        .branch(Update::filter_chat_member().endpoint(lifecycle::chat_member_handler))
         .branch(
             Update::filter_message()
+                .inspect(|db: Arc<db::Store>, msg: Message| {
+                    if msg.chat.is_group() || msg.chat.is_supergroup() {
+                        if let Err(err) = db.touch_chat(&msg.chat) {
+                            log::error!("Could not register chat: {}", err);
+                        }
+                    }
+                })
                 .branch(
-                    dptree::filter(|root: UserId, msg: Message| {
-                        msg.from().map(|user| user.id == root).unwrap_or(false)
+                    dptree::filter_map(|msg: Message| msg.migrate_to_chat_id())
+                        .endpoint(lifecycle::migration_handler),
+                )
+                .branch(
+                    dptree::filter(|config: Arc<Config>, msg: Message| {
+                        msg.from()
+                            .map(|user| config.admins.contains(&user.id))
+                            .unwrap_or(false)
                     })
                     .filter_command::<root_command::RootCommand>()
                     .endpoint(root_command::command_handler),
@@ -45,6 +133,11 @@ async fn main() -> anyhow::Result<()> {
                         .filter_command::<user_command::UserCommand>()
                         .endpoint(user_command::command_handler),
                 )
+                .branch(
+                    dptree::filter(|msg: Message| msg.chat.is_group() || msg.chat.is_supergroup())
+                        .filter_command::<admin_command::AdminCommand>()
+                        .endpoint(admin_command::command_handler),
+                )
                 .branch(
                     dptree::filter(|msg: Message| msg.chat.is_group() || msg.chat.is_supergroup())
                         .filter_command::<group_command::GroupCommand>()
@@ -56,12 +149,24 @@ async fn main() -> anyhow::Result<()> {
                 ),
         );
 
-    Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![root, store])
+    let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
+        .dependencies(dptree::deps![config.clone(), store, pinned, roles])
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
-        .await;
+        .build();
+
+    match &config.webhook {
+        Some(webhook) => {
+            let listener = webhook::listener(&bot, webhook).await?;
+            let error_handler = LoggingErrorHandler::with_custom_text("Webhook error");
+            dispatcher
+                .dispatch_with_listener(listener, error_handler)
+                .await;
+
+            // so that the bot can be switched back to polling
+            bot.delete_webhook().await?;
+        }
+        None => dispatcher.dispatch().await,
+    }
 
     Ok(())
 }
@@ -0,0 +1,106 @@
+use std::{sync::Arc, time::Duration};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter_vec, register_int_gauge,
+    Encoder, Histogram, HistogramVec, IntCounterVec, IntGauge, TextEncoder,
+};
+use teloxide::types::Message;
+
+use crate::db::Store;
+
+const COLLECT_INTERVAL: Duration = Duration::from_secs(60);
+
+pub static VOTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "karma_votes_total",
+        "Votes by result, applied or the reason they were rejected",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static CALLBACKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "karma_callbacks_total",
+        "Inline button presses by kind",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static COMMANDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("karma_commands_total", "Commands by name", &["command"]).unwrap()
+});
+
+pub static TELEGRAM_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "karma_telegram_errors_total",
+        "Telegram API errors by handler",
+        &["handler"]
+    )
+    .unwrap()
+});
+
+pub static HANDLER_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "karma_handler_duration_seconds",
+        "Time spent handling an update, by handler",
+        &["handler"]
+    )
+    .unwrap()
+});
+
+static DB_SIZE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("karma_db_size_bytes", "Size of the database on disk").unwrap()
+});
+
+static DB_FLUSH: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "karma_db_flush_duration_seconds",
+        "Time spent flushing the database to disk"
+    )
+    .unwrap()
+});
+
+static ACTIVE_CHATS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("karma_active_chats", "Chats with at least one member").unwrap()
+});
+
+// name of the command in `msg`, without the slash and the bot username
+pub fn command(msg: &Message) -> String {
+    let command = msg
+        .text()
+        .and_then(|text| text.split_whitespace().next())
+        .unwrap_or_default();
+    let command = command.split('@').next().unwrap_or_default();
+    command.trim_start_matches('/').to_lowercase()
+}
+
+fn collect(db: &Store) -> anyhow::Result<()> {
+    DB_SIZE.set(db.size_on_disk()? as i64);
+    ACTIVE_CHATS.set(db.members.len() as i64);
+
+    let timer = DB_FLUSH.start_timer();
+    db.flush()?;
+    timer.observe_duration();
+
+    Ok(())
+}
+
+// updates the metrics that are not tied to an update
+pub async fn collector(db: Arc<Store>) {
+    let mut interval = tokio::time::interval(COLLECT_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = collect(&db) {
+            log::warn!("Could not collect metrics: {}", err);
+        }
+    }
+}
+
+pub fn render() -> anyhow::Result<String> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
@@ -0,0 +1,747 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use teloxide::{
+    adaptors::DefaultParseMode,
+    payloads::SendMessageSetters,
+    requests::{Requester, ResponseResult},
+    types::{ChatId, Message, User, UserId},
+    utils::{command::BotCommands, html},
+    Bot,
+};
+use url::Url;
+
+use super::{
+    mention_stored,
+    pinned::Pinned,
+    roles::{Role, Roles},
+};
+use crate::{
+    business::{self, BanKind, AUDIT_LOG_SIZE, MAX_HOOKS, MAX_KARMA},
+    config::Config,
+    db::{random_token, Audit, Ban, Budget, ChatSettings, Hook, Store, AUDIT_TARGET},
+    events, metrics,
+};
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase")]
+pub enum AdminCommand {
+    #[command(description = "show or set the daily up and down points of this chat [admin].")]
+    Budget(String),
+    #[command(
+        description = "add karma, or remove it if negative, to the replied user or an id [admin]."
+    )]
+    AddKarma(String),
+    #[command(description = "set the karma of the replied user or an id [admin].")]
+    SetKarma(String),
+    #[command(
+        description = "reset the karma of the replied user, an id, or the chat or all [bot admin]."
+    )]
+    ResetKarma(String),
+    #[command(
+        description = "give back the daily points of the replied user, an id, or the chat or all [bot admin]."
+    )]
+    ResetPoints(String),
+    #[command(description = "show the last admin actions in this chat, or all [admin].")]
+    AuditLog(String),
+    #[command(
+        description = "stop the replied user or an id from giving, receiving or both votes, optionally for a time like 7d [admin]."
+    )]
+    Ban(String),
+    #[command(description = "lift the ban of the replied user or an id [admin].")]
+    Unban(String),
+    #[command(
+        description = "list the bans of this chat, or set whether blocked votes are answered: notify or silent [admin]."
+    )]
+    Bans(String),
+    #[command(description = "get a private api token for this chat, or revoke it [admin].")]
+    ApiToken(String),
+    #[command(description = "list, add or remove urls receiving the events of this chat [admin].")]
+    Hook(String),
+}
+
+// the user whose message the command replies to
+fn target(msg: &Message) -> Option<&User> {
+    msg.reply_to_message()
+        .and_then(|reply| reply.from())
+        .filter(|user| !user.is_bot)
+}
+
+const BAN_USAGE: &str =
+    "<i>Usage: /ban [give | receive | both] [duration like 30m, 12h, 7d or 2w]</i>";
+
+// users a karma command applies to
+enum Scope {
+    User(UserId),
+    Chat,
+    All,
+}
+
+// the replied user, or the scope given as first argument, and the other arguments
+fn scope<'a>(db: &Store, msg: &Message, args: &'a str) -> Result<(Option<Scope>, Vec<&'a str>)> {
+    let mut args = args.split_whitespace().collect::<Vec<_>>();
+
+    if let Some(user) = target(msg) {
+        db.update_profile(user)?;
+        db.add_member(msg.chat.id, user.id)?;
+        return Ok((Some(Scope::User(user.id)), args));
+    }
+
+    if args.is_empty() {
+        return Ok((None, args));
+    }
+
+    let scope = match args.remove(0) {
+        "chat" => Some(Scope::Chat),
+        "all" => Some(Scope::All),
+        id => id.parse().ok().map(|id| Scope::User(UserId(id))),
+    };
+    Ok((scope, args))
+}
+
+// chat admins can only act on the members of their chat, one at a time,
+// changes to many users at once are left to the admins of the bot
+fn allowed(db: &Store, chat: ChatId, role: Role, scope: &Scope) -> Result<bool> {
+    Ok(match scope {
+        _ if role == Role::Superadmin => true,
+        Scope::User(user) => db
+            .members
+            .get_or(chat.to_string(), HashSet::new())?
+            .contains(user),
+        Scope::Chat | Scope::All => false,
+    })
+}
+
+async fn karma(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    pinned: &Pinned,
+    msg: &Message,
+    admin: UserId,
+    role: Role,
+    cmd: AdminCommand,
+) -> Result<()> {
+    let chat = msg.chat.id;
+    let args = match &cmd {
+        AdminCommand::AddKarma(args)
+        | AdminCommand::SetKarma(args)
+        | AdminCommand::ResetKarma(args)
+        | AdminCommand::ResetPoints(args) => args.as_str(),
+        _ => return Ok(()),
+    };
+
+    let (scope, args) = scope(db, msg, args)?;
+    let scope = match scope {
+        Some(scope) => scope,
+        None => {
+            let text = match cmd {
+                AdminCommand::AddKarma(_) | AdminCommand::SetKarma(_) => {
+                    "<i>Reply to a message of the user, or give their id, and the amount.</i>"
+                }
+                _ => "<i>Reply to a message of the user, or give their id, chat or all.</i>",
+            };
+            bot.send_message(chat, text).await?;
+            return Ok(());
+        }
+    };
+
+    if !allowed(db, chat, role, &scope)? {
+        let text = match scope {
+            Scope::User(_) => "<i>This user is not a member of this chat.</i>",
+            _ => "<i>Only the admins of the bot can do that.</i>",
+        };
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let users = match scope {
+        Scope::User(user) => vec![user],
+        Scope::Chat => db
+            .members
+            .get_or(chat.to_string(), HashSet::new())?
+            .into_iter()
+            .collect(),
+        Scope::All => db
+            .karma
+            .iter()
+            .chain(db.last.iter())
+            .map(|entry| Ok(UserId(entry?.0.parse()?)))
+            .collect::<Result<HashSet<_>>>()?
+            .into_iter()
+            .collect(),
+    };
+
+    let target = match scope {
+        Scope::User(user) => Some(user),
+        _ => None,
+    };
+    let whom = match scope {
+        Scope::User(_) => AUDIT_TARGET.to_string(),
+        Scope::Chat => "every member of the chat".to_string(),
+        Scope::All => "every user".to_string(),
+    };
+
+    let amount = args.first().and_then(|amount| amount.parse::<i64>().ok());
+    let bounds = -MAX_KARMA..=MAX_KARMA;
+    if amount.is_some_and(|amount| !bounds.contains(&amount)) {
+        let text = format!(
+            "<i>The amount must be between -{} and {}.</i>",
+            MAX_KARMA, MAX_KARMA
+        );
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let action = match (&cmd, amount) {
+        (AdminCommand::AddKarma(_), Some(amount)) => format!("added {} karma to {}", amount, whom),
+        (AdminCommand::SetKarma(_), Some(amount)) => {
+            format!("set the karma of {} to {}", whom, amount)
+        }
+        (AdminCommand::ResetKarma(_), _) => format!("reset the karma of {}", whom),
+        (AdminCommand::ResetPoints(_), _) => format!("gave back the points of {}", whom),
+        _ => {
+            let text = "<i>The amount must be a number.</i>";
+            bot.send_message(chat, text).await?;
+            return Ok(());
+        }
+    };
+
+    for user in &users {
+        let previous = db.karma.get_or(user.to_string(), 0)?;
+        let karma = match (&cmd, amount) {
+            (AdminCommand::AddKarma(_), Some(amount)) => previous
+                .checked_add(amount)
+                .map_or(previous, |karma| karma.clamp(-MAX_KARMA, MAX_KARMA)),
+            (AdminCommand::SetKarma(_), Some(amount)) => amount,
+            (AdminCommand::ResetKarma(_), _) => 0,
+            _ => {
+                db.reset_points(*user)?;
+                continue;
+            }
+        };
+
+        if karma != previous {
+            db.set_karma(*user, karma)?;
+            pinned.touch(db, *user)?;
+        }
+    }
+
+    db.record_audit(Audit::new(chat, admin, target, action))?;
+
+    let text = match (scope, cmd) {
+        (Scope::User(user), AdminCommand::ResetPoints(_)) => {
+            format!("{} has all their points back.", mention_stored(db, &user)?)
+        }
+        (Scope::User(user), _) => {
+            let karma = db.karma.get_or(user.to_string(), 0)?;
+            format!("reputation of {} ({})", mention_stored(db, &user)?, karma)
+        }
+        (_, AdminCommand::ResetPoints(_)) => {
+            format!("{} users have all their points back.", users.len())
+        }
+        _ => format!("Karma of {} users updated.", users.len()),
+    };
+    bot.send_message(chat, text).await?;
+
+    Ok(())
+}
+
+async fn ban(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    msg: &Message,
+    admin: UserId,
+    role: Role,
+    cmd: AdminCommand,
+) -> Result<()> {
+    let chat = msg.chat.id;
+    let args = match &cmd {
+        AdminCommand::Ban(args) | AdminCommand::Unban(args) => args.as_str(),
+        _ => return Ok(()),
+    };
+
+    let (user, args) = match scope(db, msg, args)? {
+        (Some(Scope::User(user)), args) if allowed(db, chat, role, &Scope::User(user))? => {
+            (user, args)
+        }
+        (Some(Scope::User(_)), _) => {
+            let text = "<i>This user is not a member of this chat.</i>";
+            bot.send_message(chat, text).await?;
+            return Ok(());
+        }
+        _ => {
+            let text = "<i>Reply to a message of the user, or give their id.</i>";
+            bot.send_message(chat, text).await?;
+            return Ok(());
+        }
+    };
+
+    let mut bans = db.bans.get_or(chat.to_string(), HashMap::new())?;
+
+    if let AdminCommand::Unban(_) = cmd {
+        let text = match bans.remove(&user) {
+            Some(_) => {
+                db.bans.insert(chat.to_string(), bans)?;
+                let action = format!("lifted the ban of {}", AUDIT_TARGET);
+                db.record_audit(Audit::new(chat, admin, Some(user), action))?;
+                format!(
+                    "{} can vote in this chat again.",
+                    mention_stored(db, &user)?
+                )
+            }
+            None => "<i>This user is not banned.</i>".to_string(),
+        };
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let mut kind = BanKind::Both;
+    let mut duration = None;
+    for arg in args {
+        if let Ok(other) = BanKind::from_str(arg) {
+            kind = other;
+        } else if let Some(other) = business::parse_duration(arg) {
+            duration = Some(other);
+        } else {
+            bot.send_message(chat, BAN_USAGE).await?;
+            return Ok(());
+        }
+    }
+
+    // durations that end past the representable dates are refused too
+    let until = match duration.map(|duration| Utc::now().checked_add_signed(duration)) {
+        Some(None) => {
+            bot.send_message(chat, BAN_USAGE).await?;
+            return Ok(());
+        }
+        until => until.flatten(),
+    };
+    bans.insert(
+        user,
+        Ban {
+            kind,
+            until: until.map(|until| until.timestamp()),
+        },
+    );
+    db.bans.insert(chat.to_string(), bans)?;
+
+    let until = match until {
+        Some(until) => format!(" until {} UTC", until.format("%d/%m %H:%M")),
+        None => String::new(),
+    };
+    let action = format!("banned {} from {}{}", AUDIT_TARGET, kind, until);
+    db.record_audit(Audit::new(chat, admin, Some(user), action))?;
+
+    let text = format!(
+        "{} is banned from {} in this chat{}.",
+        mention_stored(db, &user)?,
+        kind,
+        until
+    );
+    bot.send_message(chat, text).await?;
+
+    Ok(())
+}
+
+async fn bans(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    chat: ChatId,
+    admin: UserId,
+    args: &str,
+) -> Result<()> {
+    let mut settings = db
+        .chat_settings
+        .get_or(chat.to_string(), ChatSettings::default())?;
+
+    let notify = match args.trim() {
+        "notify" => Some(true),
+        "silent" => Some(false),
+        _ => None,
+    };
+    if let Some(notify) = notify {
+        settings.notify_bans = notify;
+        db.chat_settings.insert(chat.to_string(), settings)?;
+
+        let (action, text) = match notify {
+            true => (
+                "made blocked votes answered",
+                "Blocked votes are now answered.",
+            ),
+            false => (
+                "made blocked votes ignored",
+                "Blocked votes are now ignored.",
+            ),
+        };
+        db.record_audit(Audit::new(chat, admin, None, action.to_string()))?;
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let mut bans = db
+        .bans
+        .get_or(chat.to_string(), HashMap::new())?
+        .into_iter()
+        .filter(|(_, ban)| ban.is_active())
+        .collect::<Vec<_>>();
+    bans.sort_by_key(|(user, _)| user.0);
+
+    let mut text = match settings.notify_bans {
+        true => "Bans, blocked votes are answered:\n".to_string(),
+        false => "Bans, blocked votes are ignored:\n".to_string(),
+    };
+    if bans.is_empty() {
+        text.push_str("<i>nobody is banned</i>\n");
+    }
+    for (user, ban) in bans {
+        text.push_str(&format!("{} from {}", mention_stored(db, &user)?, ban.kind));
+        if let Some(until) = ban.until {
+            let until = Utc.timestamp_opt(until, 0).unwrap().format("%d/%m %H:%M");
+            text.push_str(&format!(" until {} UTC", until));
+        }
+        text.push('\n');
+    }
+
+    bot.send_message(chat, text).await?;
+
+    Ok(())
+}
+
+async fn audit_log(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    chat: ChatId,
+    role: Role,
+    args: &str,
+) -> Result<()> {
+    let all = args.trim() == "all";
+    if all && role != Role::Superadmin {
+        let text = "<i>Only the admins of the bot can do that.</i>";
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let log = db.audit_log(Some(chat).filter(|_| !all), AUDIT_LOG_SIZE)?;
+    if log.is_empty() {
+        let text = "<i>No admin actions yet.</i>";
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let mut text = "Admin actions:\n".to_string();
+    for audit in log {
+        let time = Utc
+            .timestamp_opt(audit.timestamp, 0)
+            .unwrap()
+            .format("%d/%m %H:%M");
+        text.push_str(&format!("{} ", time));
+        if all {
+            text.push_str(&format!("[{}] ", audit.chat));
+        }
+        // the target is only named now, so that forgotten users stay forgotten
+        let mut action = html::escape(&audit.action);
+        if let Some(target) = audit.target {
+            action = action.replace(AUDIT_TARGET, &mention_stored(db, &target)?);
+        }
+        text.push_str(&format!(
+            "{} {}\n",
+            mention_stored(db, &audit.admin)?,
+            action
+        ));
+    }
+
+    bot.send_message(chat, text).await?;
+
+    Ok(())
+}
+
+async fn budget(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    config: &Config,
+    chat: ChatId,
+    admin: UserId,
+    args: &str,
+) -> Result<()> {
+    let args = args.split_whitespace().collect::<Vec<_>>();
+    let text = match args[..] {
+        [] => {
+            let budget = db
+                .budgets
+                .get_or(chat.to_string(), config.defaults.budget())?;
+            format!(
+                "Members get {} up and {} down points a day.",
+                budget.up, budget.down
+            )
+        }
+        ["reset"] => {
+            db.budgets.remove(chat.to_string())?;
+            let action = "reset the budget".to_string();
+            db.record_audit(Audit::new(chat, admin, None, action))?;
+            let budget = config.defaults.budget();
+            format!(
+                "Members get the default {} up and {} down points a day.",
+                budget.up, budget.down
+            )
+        }
+        [up, down] => match (up.parse::<i64>(), down.parse::<i64>()) {
+            (Ok(up), Ok(down)) if up >= 0 && down >= 0 => {
+                db.budgets.insert(chat.to_string(), Budget { up, down })?;
+                let action = format!("set the budget to {} up and {} down", up, down);
+                db.record_audit(Audit::new(chat, admin, None, action))?;
+                format!("Members now get {} up and {} down points a day.", up, down)
+            }
+            _ => "<i>Points must be non-negative numbers.</i>".to_string(),
+        },
+        _ => "<i>Usage: /budget [up down | reset]</i>".to_string(),
+    };
+
+    bot.send_message(chat, text).await?;
+
+    Ok(())
+}
+
+async fn hook(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    chat: ChatId,
+    admin: UserId,
+    args: &str,
+) -> Result<()> {
+    let mut hooks = db.hooks.get_or(chat.to_string(), vec![])?;
+    let mut args = args.split_whitespace();
+
+    let text = match (args.next(), args.next()) {
+        (Some("add"), Some(url)) => match Url::parse(url) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => {
+                if hooks.iter().any(|hook| hook.url == url.as_str()) {
+                    "<i>This url is already registered.</i>".to_string()
+                } else if hooks.len() >= MAX_HOOKS {
+                    format!("<i>A chat can have at most {} hooks.</i>", MAX_HOOKS)
+                } else if let Err(err) = events::resolve(&url, events::is_public).await {
+                    format!(
+                        "<i>Hooks need a public address: {}.</i>",
+                        html::escape(&err.to_string())
+                    )
+                } else {
+                    // secrets are only ever sent privately
+                    let secret = random_token()?;
+                    let text = format!(
+                        "Signing secret for {}: <code>{}</code>",
+                        html::escape(url.as_str()),
+                        secret
+                    );
+                    match bot.send_message(admin, text).await {
+                        Ok(_) => {
+                            hooks.push(Hook {
+                                url: url.to_string(),
+                                secret,
+                            });
+                            db.hooks.insert(chat.to_string(), hooks)?;
+                            let action = format!("added the hook {}", url);
+                            db.record_audit(Audit::new(chat, admin, None, action))?;
+                            "<i>Hook added, I sent you its secret privately.</i>".to_string()
+                        }
+                        Err(_) => {
+                            "<i>Start a private chat with me first, then try again.</i>".to_string()
+                        }
+                    }
+                }
+            }
+            _ => "<i>Hooks need an http or https url.</i>".to_string(),
+        },
+        (Some("remove"), Some(url)) => {
+            // urls are stored normalized, as added
+            let url = Url::parse(url).map_or(url.to_string(), String::from);
+            let count = hooks.len();
+            hooks.retain(|hook| hook.url != url);
+            if hooks.len() == count {
+                "<i>This url is not registered.</i>".to_string()
+            } else {
+                db.hooks.insert(chat.to_string(), hooks)?;
+                let action = format!("removed the hook {}", url);
+                db.record_audit(Audit::new(chat, admin, None, action))?;
+                "<i>Hook removed.</i>".to_string()
+            }
+        }
+        (None, _) | (Some("list"), _) => match hooks.is_empty() {
+            true => "<i>This chat has no hooks.</i>".to_string(),
+            false => {
+                let urls = hooks
+                    .iter()
+                    .map(|hook| html::escape(&hook.url))
+                    .collect::<Vec<_>>();
+                format!("Hooks:\n{}", urls.join("\n"))
+            }
+        },
+        _ => "<i>Usage: /hook [list | add url | remove url]</i>".to_string(),
+    };
+
+    bot.send_message(chat, text)
+        .disable_web_page_preview(true)
+        .await?;
+
+    Ok(())
+}
+
+async fn handler(
+    bot: DefaultParseMode<Bot>,
+    db: Arc<Store>,
+    config: Arc<Config>,
+    pinned: Arc<Pinned>,
+    roles: Arc<Roles>,
+    msg: Message,
+    cmd: AdminCommand,
+) -> Result<()> {
+    let sender = match msg.from() {
+        Some(sender) => sender,
+        None => return Ok(()),
+    };
+
+    let role = roles.of(&bot, &config, msg.chat.id, sender.id).await?;
+    if role < Role::ChatAdmin {
+        let text = "<i>Only chat admins can do that.</i>";
+        bot.send_message(msg.chat.id, text).await?;
+        return Ok(());
+    }
+
+    // names of admins are shown in the audit log
+    db.update_profile(sender)?;
+
+    match cmd {
+        AdminCommand::Budget(args) => {
+            budget(&bot, &db, &config, msg.chat.id, sender.id, &args).await?;
+        }
+        AdminCommand::AddKarma(_)
+        | AdminCommand::SetKarma(_)
+        | AdminCommand::ResetKarma(_)
+        | AdminCommand::ResetPoints(_) => {
+            karma(&bot, &db, &pinned, &msg, sender.id, role, cmd).await?;
+        }
+        AdminCommand::AuditLog(args) => {
+            audit_log(&bot, &db, msg.chat.id, role, &args).await?;
+        }
+        AdminCommand::Ban(_) | AdminCommand::Unban(_) => {
+            ban(&bot, &db, &msg, sender.id, role, cmd).await?;
+        }
+        AdminCommand::Bans(args) => {
+            bans(&bot, &db, msg.chat.id, sender.id, &args).await?;
+        }
+        AdminCommand::ApiToken(args) => {
+            if args.trim() == "revoke" {
+                db.revoke_api_token(msg.chat.id)?;
+                let action = "revoked the api token".to_string();
+                db.record_audit(Audit::new(msg.chat.id, sender.id, None, action))?;
+                let text = "<i>The api token of this chat was revoked.</i>";
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+
+            // tokens are only ever sent privately
+            let token = db.issue_api_token(msg.chat.id)?;
+            let text = format!(
+                "Api token for {}: <code>{}</code>\n\
+                Any previous token of the chat no longer works.",
+                html::escape(msg.chat.title().unwrap_or_default()),
+                token
+            );
+            let text = match bot.send_message(sender.id, text).await {
+                Ok(_) => {
+                    let action = "issued an api token".to_string();
+                    db.record_audit(Audit::new(msg.chat.id, sender.id, None, action))?;
+                    "<i>I sent you the api token privately.</i>"
+                }
+                Err(_) => {
+                    db.revoke_api_token(msg.chat.id)?;
+                    "<i>Start a private chat with me first, then try again.</i>"
+                }
+            };
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        AdminCommand::Hook(args) => {
+            hook(&bot, &db, msg.chat.id, sender.id, &args).await?;
+        }
+    };
+
+    Ok(())
+}
+
+pub async fn command_handler(
+    bot: DefaultParseMode<Bot>,
+    db: Arc<Store>,
+    config: Arc<Config>,
+    pinned: Arc<Pinned>,
+    roles: Arc<Roles>,
+    msg: Message,
+    cmd: AdminCommand,
+) -> ResponseResult<()> {
+    metrics::COMMANDS
+        .with_label_values(&[&metrics::command(&msg)])
+        .inc();
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["admin_command"])
+        .start_timer();
+    match handler(bot, db, config, pinned, roles, msg, cmd).await {
+        Ok(_) => Ok(()),
+        Err(err) => match err.downcast::<teloxide::RequestError>() {
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["admin_command"])
+                    .inc();
+                Err(err)
+            }
+            Err(err) => {
+                log::error!("Generic error: {}", err);
+                Ok(())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: ChatId = ChatId(-100);
+    const OTHER_CHAT: ChatId = ChatId(-200);
+    const MEMBER: UserId = UserId(1);
+    const STRANGER: UserId = UserId(2);
+
+    fn store() -> Result<Store> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let store = Store::new(&db)?;
+        store.add_member(CHAT, MEMBER)?;
+        store.add_member(OTHER_CHAT, STRANGER)?;
+        Ok(store)
+    }
+
+    #[test]
+    fn chat_admins_only_act_on_members_of_their_chat() -> Result<()> {
+        let db = store()?;
+        let role = Role::ChatAdmin;
+
+        assert!(allowed(&db, CHAT, role, &Scope::User(MEMBER))?);
+        assert!(!allowed(&db, CHAT, role, &Scope::User(STRANGER))?);
+        assert!(!allowed(&db, CHAT, role, &Scope::User(UserId(42)))?);
+        assert!(!allowed(&db, CHAT, role, &Scope::Chat)?);
+        assert!(!allowed(&db, CHAT, role, &Scope::All)?);
+        Ok(())
+    }
+
+    #[test]
+    fn admins_of_the_bot_act_on_anyone() -> Result<()> {
+        let db = store()?;
+        let role = Role::Superadmin;
+
+        assert!(allowed(&db, CHAT, role, &Scope::User(MEMBER))?);
+        assert!(allowed(&db, CHAT, role, &Scope::User(STRANGER))?);
+        assert!(allowed(&db, CHAT, role, &Scope::Chat)?);
+        assert!(allowed(&db, CHAT, role, &Scope::All)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use bincode::{deserialize, serialize};
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, UserId};
+
+use crate::business::Karma;
+
+// base64 never contains ':', so old vote buttons are told apart by its absence
+const PREFIX: &str = "1:";
+
+#[derive(Serialize, Deserialize)]
+pub enum Callback {
+    Vote(Karma, UserId),
+    History {
+        user: UserId,
+        chat: Option<ChatId>,
+        page: usize,
+    },
+    Stats {
+        chat: Option<ChatId>,
+    },
+    // answer to the confirmation of /forgetme
+    Forget {
+        confirm: bool,
+    },
+}
+
+impl Callback {
+    pub fn encode(&self) -> Result<String> {
+        Ok(format!("{}{}", PREFIX, base64::encode(serialize(self)?)))
+    }
+
+    pub fn decode(data: &str) -> Result<Self> {
+        match data.strip_prefix(PREFIX) {
+            Some(data) => Ok(deserialize(&base64::decode(data)?)?),
+            // buttons sent before callbacks had variants only carried votes
+            None => {
+                let (karma, user) = deserialize(&base64::decode(data)?)?;
+                Ok(Callback::Vote(karma, user))
+            }
+        }
+    }
+}
@@ -1,63 +1,289 @@
-use std::{collections::HashSet, env, path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
 
-use anyhow::{Error, Result};
-use chrono::{TimeZone, Utc};
-use plotters::prelude::*;
+use anyhow::Result;
+use chrono::{Duration, Utc};
 use teloxide::{
     adaptors::DefaultParseMode,
-    payloads::SendPhotoSetters,
+    payloads::{
+        SendDocumentSetters, SendMessageSetters, SendPhotoSetters, UnpinChatMessageSetters,
+    },
     requests::{Requester, ResponseResult},
-    types::{InputFile, Message},
-    utils::command::BotCommands,
+    types::{ChatId, InputFile, InputMedia, InputMediaPhoto, Message, MessageEntityKind, UserId},
+    utils::{command::BotCommands, html},
     Bot,
 };
-use tokio::fs;
 
-use super::{mention_chat, mention_id, mention_user};
-use crate::db::{Measure, Store};
+use super::{
+    display_name, history, mention_chat, mention_id, mention_profile, mention_stored, mention_user,
+    roles::{Role, Roles},
+};
+use crate::{
+    business::{self, Givers, Tally, LEADERBOARD_SIZE},
+    chart::{self, CardRow, ChartRange, Format},
+    config::Config,
+    db::Store,
+    metrics,
+};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 pub enum GroupCommand {
-    #[command(description = "display leaderboard.")]
-    Leaderboard,
-    #[command(description = "display graph.")]
-    Chart,
+    #[command(
+        description = "display leaderboard as text or image, all to include who left, pin or unpin a live one, or rank givers."
+    )]
+    Leaderboard(String),
+    #[command(description = "display graph, optionally for @users, week, month or all, as svg.")]
+    Chart(String),
+    #[command(description = "display group statistics for week, month or all.")]
+    GroupStats(String),
+    #[command(
+        description = "show or change chart settings: theme, size, style, votes, timezone [admin to change]."
+    )]
+    ChartSettings(String),
+    #[command(description = "display karma, rank and weekly votes of a user.")]
+    Karma(String),
+    #[command(description = "display the last votes given and received by a user.")]
+    History,
+}
+
+// departed members are only listed with `all`, marked as left
+pub(crate) async fn leaderboard_text(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    chat: ChatId,
+    all: bool,
+) -> Result<Option<String>> {
+    let leaderboard = db.leaderboard(chat, all, LEADERBOARD_SIZE)?;
+
+    if leaderboard.is_empty() {
+        return Ok(None);
+    }
+
+    let week = (Utc::now() - Duration::weeks(1)).timestamp();
+    let departures = db.departures.get_or(chat.to_string(), HashMap::new())?;
+
+    let mut text = String::new();
+    for (i, (id, karma)) in leaderboard.iter().enumerate() {
+        if let Ok(chat) = bot.get_chat(*id).await {
+            let mention = mention_chat(&chat);
+            text.push_str(&format!("{}. {} : {}", i + 1, mention, karma));
+        } else {
+            let mention = mention_id(id);
+            text.push_str(&format!("{}. {} : {}", i + 1, mention, karma));
+        }
+
+        if let Some(trend) = chart::sparkline(db.history.since(*id, week)?, week) {
+            text.push_str(&format!(" {}", trend.line));
+        }
+        if departures.contains_key(id) {
+            text.push_str(" <i>(left)</i>");
+        }
+        text.push('\n');
+    }
+
+    Ok(Some(text))
+}
+
+async fn leaderboard_image(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    config: &Config,
+    chat: ChatId,
+    all: bool,
+) -> Result<()> {
+    let leaderboard = db.leaderboard(chat, all, LEADERBOARD_SIZE)?;
+
+    if leaderboard.is_empty() {
+        let text = "<i>There are no members with karma in this group.</i>";
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let week = (Utc::now() - Duration::weeks(1)).timestamp();
+    let departures = db.departures.get_or(chat.to_string(), HashMap::new())?;
+    let mut rows = vec![];
+    for (i, (id, karma)) in leaderboard.into_iter().enumerate() {
+        let history = db.history.since(id, week)?;
+
+        // users without history before the last week started from zero
+        let start = match history.first() {
+            Some(first) if first.timestamp < week => first.karma,
+            _ => 0,
+        };
+
+        let mut name = display_name(bot, db, id).await?;
+        if departures.contains_key(&id) {
+            name.push_str(" (left)");
+        }
+
+        rows.push(CardRow {
+            rank: i + 1,
+            name,
+            karma,
+            delta: karma - start,
+            trend: chart::trend(history, week),
+        });
+    }
+
+    let settings = db
+        .chart_settings
+        .get_or(chat.to_string(), config.defaults.chart_settings())?;
+    let card = chart::leaderboard_card(&rows, &settings)?;
+    let file = InputFile::memory(card).file_name("leaderboard.png");
+
+    let last_message_key = format!("{}-leaderboard", chat);
+    if let Some(last_message) = db.last_message.get(&last_message_key)? {
+        bot.delete_message(chat, last_message).await.ok();
+    }
+
+    let message = bot.send_photo(chat, file).await?;
+    db.last_message.insert(&last_message_key, message.id)?;
+
+    Ok(())
+}
+
+async fn givers(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    chat: ChatId,
+    args: Vec<&str>,
+) -> Result<()> {
+    let mut kind = Givers::Up;
+    let mut range = ChartRange::All;
+    for arg in args {
+        if let Ok(other) = Givers::from_str(arg) {
+            kind = other;
+        } else if let Ok(other) = ChartRange::from_str(arg) {
+            range = other;
+        }
+    }
+
+    let since = range.since().map(|since| since.timestamp());
+    let votes = db.votes.since(chat, since.unwrap_or(i64::MIN))?;
+    let scores = kind.rank(&votes);
+
+    if scores.is_empty() {
+        let text = "<i>Nobody voted in this group yet.</i>";
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
+
+    let mut text = format!("Givers, {}:\n", kind);
+    for (i, (id, score)) in scores.into_iter().take(LEADERBOARD_SIZE).enumerate() {
+        let score = match kind {
+            Givers::Ratio => format!("{:.2}", score),
+            _ => score.to_string(),
+        };
+        let mention = mention_stored(db, &id)?;
+        text.push_str(&format!("{}. {} : {}\n", i + 1, mention, score));
+    }
+
+    let last_message_key = format!("{}-leaderboard", chat);
+    if let Some(last_message) = db.last_message.get(&last_message_key)? {
+        bot.delete_message(chat, last_message).await.ok();
+    }
+
+    let message = bot.send_message(chat, text).await?;
+    db.last_message.insert(&last_message_key, message.id)?;
+
+    Ok(())
 }
 
-const MARGIN: i32 = 10;
-const LABEL_AREA: i32 = 40;
+// answers members that are not chat admins, so that only admins change the chat
+async fn require_admin(
+    bot: &DefaultParseMode<Bot>,
+    config: &Config,
+    roles: &Roles,
+    msg: &Message,
+) -> Result<bool> {
+    let role = match msg.from() {
+        Some(sender) => roles.of(bot, config, msg.chat.id, sender.id).await?,
+        None => Role::Member,
+    };
 
-fn graph(path: &PathBuf, data: Vec<Measure>) -> Result<()> {
-    let root = BitMapBackend::new(path, (640, 480)).into_drawing_area();
+    if role < Role::ChatAdmin {
+        let text = "<i>Only chat admins can do that.</i>";
+        bot.send_message(msg.chat.id, text).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+async fn pin(bot: &DefaultParseMode<Bot>, db: &Store, chat: ChatId) -> Result<()> {
+    if db.pinned.get(chat.to_string())?.is_some() {
+        let text = "<i>The leaderboard is already pinned.</i>";
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
 
-    root.fill(&WHITE)?;
-    let surface = root.margin(MARGIN + LABEL_AREA, MARGIN, MARGIN, MARGIN + LABEL_AREA);
+    let text = leaderboard_text(bot, db, chat, false)
+        .await?
+        .unwrap_or_else(|| "<i>There are no members with karma in this group.</i>".to_string());
 
-    let max_karma = data.iter().map(|m| m.karma).max().unwrap_or(0) + 1;
-    let min_karma = data.iter().map(|m| m.karma).min().unwrap_or(0) - 1;
+    let message = bot.send_message(chat, text).await?;
+    if bot.pin_chat_message(chat, message.id).await.is_err() {
+        bot.delete_message(chat, message.id).await.ok();
+        let text = "<i>I need permission to pin messages to do that.</i>";
+        bot.send_message(chat, text).await?;
+        return Ok(());
+    }
 
-    let mut chart = ChartBuilder::on(&surface)
-        .set_label_area_size(LabelAreaPosition::Left, LABEL_AREA)
-        .set_label_area_size(LabelAreaPosition::Bottom, LABEL_AREA)
-        .build_cartesian_2d(0..(data.len() - 1), min_karma..max_karma)?;
+    db.pinned.insert(chat.to_string(), message.id)?;
+
+    Ok(())
+}
+
+async fn unpin(bot: &DefaultParseMode<Bot>, db: &Store, chat: ChatId) -> Result<()> {
+    if let Some(message) = db.pinned.remove(chat.to_string())? {
+        bot.unpin_chat_message(chat).message_id(message).await.ok();
+        bot.delete_message(chat, message).await.ok();
+    }
+
+    Ok(())
+}
+
+async fn karma(bot: &DefaultParseMode<Bot>, db: &Store, chat: ChatId, user: UserId) -> Result<()> {
+    let karma = db.karma.get_or(user.to_string(), 0)?;
+    let mut text = format!("karma of {} ({})", mention_stored(db, &user)?, karma);
+
+    let memberships = db.memberships.get_or(user.to_string(), HashSet::new())?;
+    if memberships.contains(&chat) {
+        let rank = db.ranking.rank(chat, user, karma)?;
+        text.push_str(&format!(", #{} in this chat", rank));
+    }
+
+    let week = (Utc::now() - Duration::weeks(1)).timestamp();
+    if let Some(trend) = chart::sparkline(db.history.since(user, week)?, week) {
+        text.push_str(&format!("\n{} this week", trend));
+    }
+
+    let tally = Tally::new(&db.votes.since(chat, week)?, user);
+    text.push_str(&format!(
+        "\nreceived this week: {} +, {} -\n\
+        given this week: {} +, {} -",
+        tally.received_up, tally.received_down, tally.given_up, tally.given_down
+    ));
+
+    let givers = business::top_givers(&db.votes.since(chat, i64::MIN)?, user);
+    if !givers.is_empty() {
+        let mut mentions = vec![];
+        for (id, net) in givers {
+            mentions.push(format!("{} ({:+})", mention_stored(db, &id)?, net));
+        }
+        text.push_str(&format!("\ntop givers: {}", mentions.join(", ")));
+    }
 
-    chart
-        .configure_mesh()
-        .x_label_formatter(&|x| {
-            format!(
-                "{}",
-                Utc.timestamp(data[*x].timestamp, 0).format("%d/%m %H:%M")
-            )
-        })
-        .y_desc("karma")
-        .x_desc("time")
-        .draw()?;
+    let last_message_key = format!("{}-karma", chat);
+    if let Some(last_message) = db.last_message.get(&last_message_key)? {
+        bot.delete_message(chat, last_message).await.ok();
+    }
 
-    chart.draw_series(LineSeries::new(
-        (0..).zip(data.iter()).map(|(i, m)| (i, m.karma)),
-        BLUE,
-    ))?;
+    let message = bot.send_message(chat, text).await?;
+    db.last_message.insert(&last_message_key, message.id)?;
 
     Ok(())
 }
@@ -65,39 +291,53 @@ fn graph(path: &PathBuf, data: Vec<Measure>) -> Result<()> {
 async fn handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
+    roles: Arc<Roles>,
     msg: Message,
     cmd: GroupCommand,
 ) -> Result<()> {
     match cmd {
-        GroupCommand::Leaderboard => {
-            let members = db.members.get_or(msg.chat.id.to_string(), HashSet::new())?;
-
-            if members.is_empty() {
-                let text = "<i>There are no members with karma in this group.</i>";
-                bot.send_message(msg.chat.id, text).await?;
-                return Ok(());
+        GroupCommand::Leaderboard(mode) => {
+            let mut args = mode.split_whitespace();
+            let first = args.next().unwrap_or_default();
+            match first {
+                "givers" => return givers(&bot, &db, msg.chat.id, args.collect()).await,
+                "pin" | "unpin" => {
+                    if !require_admin(&bot, &config, &roles, &msg).await? {
+                        return Ok(());
+                    }
+                    return match first {
+                        "pin" => pin(&bot, &db, msg.chat.id).await,
+                        _ => unpin(&bot, &db, msg.chat.id).await,
+                    };
+                }
+                "image" => {
+                    let all = args.next() == Some("all");
+                    return leaderboard_image(&bot, &db, &config, msg.chat.id, all).await;
+                }
+                _ => {}
             }
 
-            let mut leaderboard = members
-                .iter()
-                .map(|id| {
-                    let karma = db.karma.get_or(id.to_string(), 0)?;
-                    Ok((id, karma))
-                })
-                .collect::<Result<Vec<_>, Error>>()?;
-
-            leaderboard.sort_by(|(_, a), (_, b)| b.cmp(a));
-
-            let mut text = String::new();
-            for (i, (id, karma)) in leaderboard.iter().enumerate() {
-                if let Ok(chat) = bot.get_chat(**id).await {
-                    let mention = mention_chat(&chat);
-                    text.push_str(&format!("{}. {} : {}", i + 1, mention, karma));
-                    text.push('\n');
-                } else {
-                    let mention = mention_id(id);
-                    text.push_str(&format!("{}. {} : {}", i + 1, mention, karma));
-                    text.push('\n');
+            let all = first == "all";
+            let mut text = match leaderboard_text(&bot, &db, msg.chat.id, all).await? {
+                Some(text) => text,
+                None => {
+                    let text = "<i>There are no members with karma in this group.</i>";
+                    bot.send_message(msg.chat.id, text).await?;
+                    return Ok(());
+                }
+            };
+
+            // show the position of whoever asked if they are not in the top
+            if let Some(sender) = msg.from() {
+                let memberships = db
+                    .memberships
+                    .get_or(sender.id.to_string(), HashSet::new())?;
+                let karma = db.karma.get_or(sender.id.to_string(), 0)?;
+                let rank = db.ranking.rank(msg.chat.id, sender.id, karma)?;
+                if memberships.contains(&msg.chat.id) && rank > LEADERBOARD_SIZE {
+                    let mention = mention_user(sender);
+                    text.push_str(&format!("...\n{}. {} : {}\n", rank, mention, karma));
                 }
             }
 
@@ -109,39 +349,247 @@ async fn handler(
             let message = bot.send_message(msg.chat.id, text).await?;
             db.last_message.insert(&last_message_key, message.id)?;
         }
-        GroupCommand::Chart => {
-            if let Some(mut user) = msg.from() {
-                // if command is a reply to a message by another user, use that user
-                if let Some(reply) = msg.reply_to_message() {
-                    if let Some(other) = reply.from() {
-                        user = other;
+        GroupCommand::Chart(args) => {
+            let mut range = ChartRange::All;
+            let settings = db
+                .chart_settings
+                .get_or(msg.chat.id.to_string(), config.defaults.chart_settings())?;
+            let mut format = Format::Png;
+            let mut users = vec![];
+            let mut unknown = vec![];
+
+            for arg in args.split_whitespace() {
+                if let Ok(other) = ChartRange::from_str(arg) {
+                    range = other;
+                } else if let Ok(other) = Format::from_str(arg) {
+                    format = other;
+                } else if arg.starts_with('@') {
+                    match db.find_username(arg)? {
+                        Some(id) => users.push(id),
+                        None => unknown.push(arg),
                     }
                 }
+            }
 
-                let data = db.graph.get_or(user.id.to_string(), vec![])?;
+            // users without a username can only be mentioned by their name
+            for entity in msg.entities().unwrap_or_default() {
+                if let MessageEntityKind::TextMention { user } = &entity.kind {
+                    users.push(user.id);
+                }
+            }
 
-                if data.len() < 2 {
-                    let text = "<i>There is no data to display.</i>";
-                    bot.send_message(msg.chat.id, text).await?;
-                    return Ok(());
+            if !unknown.is_empty() {
+                let text = format!(
+                    "<i>I don't know {} yet.</i>",
+                    html::escape(&unknown.join(", "))
+                );
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+
+            if users.is_empty() {
+                // if command is a reply to a message by another user, use that user
+                let user = msg
+                    .reply_to_message()
+                    .and_then(|reply| reply.from())
+                    .or_else(|| msg.from());
+                users.extend(user.map(|user| user.id));
+            }
+
+            let mut seen = HashSet::new();
+            users.retain(|id| seen.insert(*id));
+
+            let mut series = vec![];
+            let mut mentions = vec![];
+            for id in users {
+                let since = range.since().map(|since| since.timestamp());
+                let data = db.history.since(id, since.unwrap_or(i64::MIN))?;
+                if data.is_empty() {
+                    continue;
+                }
+
+                let profile = db.profiles.get(id.to_string())?;
+                let name = profile
+                    .as_ref()
+                    .map(|profile| profile.to_string())
+                    .unwrap_or_else(|| id.to_string());
+
+                mentions.push(match profile {
+                    Some(profile) => mention_profile(&id, &profile),
+                    None => mention_id(&id),
+                });
+                series.push((name, data));
+            }
+
+            if series.is_empty() {
+                let text = "<i>There is no data to display.</i>";
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
+
+            let name = format!("karma-{}", msg.chat.id);
+            let file = InputFile::memory(chart::render(series, range, format, &settings)?)
+                .file_name(format.file_name(&name));
+            let caption = format!("Karma chart for {}", mentions.join(", "));
+
+            let last_message_key = format!("{}-chart", msg.chat.id);
+            if let Some(last_message) = db.last_message.get(&last_message_key)? {
+                bot.delete_message(msg.chat.id, last_message).await.ok();
+            }
+
+            // svg is not a supported photo format, send it as a file instead
+            let message = match format {
+                Format::Png => bot.send_photo(msg.chat.id, file).caption(caption).await?,
+                Format::Svg => {
+                    bot.send_document(msg.chat.id, file)
+                        .caption(caption)
+                        .await?
                 }
+            };
+            db.last_message.insert(&last_message_key, message.id)?;
+        }
+        GroupCommand::GroupStats(args) => {
+            let range = ChartRange::from_str(args.trim()).unwrap_or(ChartRange::Month);
+            let settings = db
+                .chart_settings
+                .get_or(msg.chat.id.to_string(), config.defaults.chart_settings())?;
+
+            let since = range.since().map(|since| since.timestamp());
+            let votes = db.votes.since(msg.chat.id, since.unwrap_or(i64::MIN))?;
+
+            if votes.is_empty() {
+                let text = "<i>There is no data to display.</i>";
+                bot.send_message(msg.chat.id, text).await?;
+                return Ok(());
+            }
 
-                let path = env::temp_dir().join(format!("{}.png", user.id));
+            let since = since.unwrap_or(votes[0].timestamp);
+            let karma = db
+                .ranking
+                .top(msg.chat.id, usize::MAX)?
+                .into_iter()
+                .map(|(_, karma)| karma)
+                .collect::<Vec<_>>();
 
-                graph(&path, data)?;
+            let charts = vec![
+                chart::votes_per_day(&votes, since, &settings)?,
+                chart::karma_distribution(&karma, &settings)?,
+                chart::up_ratio(&votes, since, &settings)?,
+                chart::activity(&votes, &settings)?,
+            ];
 
-                let file = InputFile::file(&path);
-                let caption = format!("Karma chart for {}", mention_user(user));
+            let caption = format!("Group stats ({} votes)", votes.len());
+            let media = charts.into_iter().enumerate().map(|(i, chart)| {
+                let file = InputFile::memory(chart).file_name(format!("stats-{}.png", i));
+                let photo = match i {
+                    0 => InputMediaPhoto::new(file).caption(caption.clone()),
+                    _ => InputMediaPhoto::new(file),
+                };
+                InputMedia::Photo(photo)
+            });
 
-                let last_message_key = format!("{}-chart", msg.chat.id);
+            // albums can only be deleted one message at a time
+            let last_message_key = format!("{}-groupstats", msg.chat.id);
+            for i in 0..4 {
+                let key = format!("{}-{}", last_message_key, i);
+                if let Some(last_message) = db.last_message.remove(&key)? {
+                    bot.delete_message(msg.chat.id, last_message).await.ok();
+                }
+            }
+
+            let messages = bot.send_media_group(msg.chat.id, media).await?;
+            for (i, message) in messages.iter().enumerate() {
+                let key = format!("{}-{}", last_message_key, i);
+                db.last_message.insert(&key, message.id)?;
+            }
+        }
+        GroupCommand::ChartSettings(args) => {
+            let mut settings = db
+                .chart_settings
+                .get_or(msg.chat.id.to_string(), config.defaults.chart_settings())?;
+
+            let change = args.split_once(' ');
+            if change.is_some() && !require_admin(&bot, &config, &roles, &msg).await? {
+                return Ok(());
+            }
+
+            let text = match change {
+                Some((key, value)) => match settings.set(key.trim(), value.trim()) {
+                    Ok(()) => {
+                        db.chart_settings
+                            .insert(msg.chat.id.to_string(), settings.clone())?;
+                        format!("Chart settings updated: \n{}", settings)
+                    }
+                    Err(err) => format!("<i>{}.</i>", err),
+                },
+                None => format!("Chart settings: \n{}", settings),
+            };
+
+            bot.send_message(msg.chat.id, text).await?;
+        }
+        GroupCommand::Karma(args) => {
+            let user = match args.split_whitespace().find(|arg| arg.starts_with('@')) {
+                Some(username) => match db.find_username(username)? {
+                    Some(id) => Some(id),
+                    None => {
+                        let text = format!("<i>I don't know {} yet.</i>", html::escape(username));
+                        bot.send_message(msg.chat.id, text).await?;
+                        return Ok(());
+                    }
+                },
+                // users without a username can only be mentioned by their name
+                None => msg
+                    .entities()
+                    .unwrap_or_default()
+                    .iter()
+                    .find_map(|entity| match &entity.kind {
+                        MessageEntityKind::TextMention { user } => Some(user.id),
+                        _ => None,
+                    }),
+            };
+
+            // if command is a reply to a message by another user, use that user
+            let user = match user {
+                Some(id) => Some(id),
+                None => match msg
+                    .reply_to_message()
+                    .and_then(|reply| reply.from())
+                    .or_else(|| msg.from())
+                {
+                    Some(user) => {
+                        db.update_profile(user)?;
+                        Some(user.id)
+                    }
+                    None => None,
+                },
+            };
+
+            if let Some(user) = user {
+                karma(&bot, &db, msg.chat.id, user).await?;
+            }
+        }
+        GroupCommand::History => {
+            // if command is a reply to a message by another user, use that user
+            let user = msg
+                .reply_to_message()
+                .and_then(|reply| reply.from())
+                .or_else(|| msg.from());
+
+            if let Some(user) = user {
+                db.update_profile(user)?;
+                let (text, keyboard) = history::page(&db, user.id, Some(msg.chat.id), 0)?;
+
+                let last_message_key = format!("{}-history", msg.chat.id);
                 if let Some(last_message) = db.last_message.get(&last_message_key)? {
                     bot.delete_message(msg.chat.id, last_message).await.ok();
                 }
 
-                let message = bot.send_photo(msg.chat.id, file).caption(caption).await?;
+                let message = bot
+                    .send_message(msg.chat.id, text)
+                    .reply_markup(keyboard)
+                    .disable_web_page_preview(true)
+                    .await?;
                 db.last_message.insert(&last_message_key, message.id)?;
-
-                fs::remove_file(&path).await?;
             }
         }
     };
@@ -152,13 +600,26 @@ async fn handler(
 pub async fn command_handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
+    roles: Arc<Roles>,
     msg: Message,
     cmd: GroupCommand,
 ) -> ResponseResult<()> {
-    match handler(bot, db, msg, cmd).await {
+    metrics::COMMANDS
+        .with_label_values(&[&metrics::command(&msg)])
+        .inc();
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["group_command"])
+        .start_timer();
+    match handler(bot, db, config, roles, msg, cmd).await {
         Ok(_) => Ok(()),
         Err(err) => match err.downcast::<teloxide::RequestError>() {
-            Ok(err) => Err(err),
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["group_command"])
+                    .inc();
+                Err(err)
+            }
             Err(err) => {
                 log::error!("Generic error: {}", err);
                 Ok(())
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use teloxide::{
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, UserId},
+    utils::html,
+};
+
+use super::{callback::Callback, mention_stored};
+use crate::{business::HISTORY_PAGE_SIZE, db::Store};
+
+// a page of the votes given and received by `user`, newest first
+pub(crate) fn page(
+    db: &Store,
+    user: UserId,
+    chat: Option<ChatId>,
+    page: usize,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    // one more vote than needed tells whether there is a next page
+    let mut votes = db
+        .votes
+        .of(user, chat, page * HISTORY_PAGE_SIZE, HISTORY_PAGE_SIZE + 1)?;
+    let more = votes.len() > HISTORY_PAGE_SIZE;
+    votes.truncate(HISTORY_PAGE_SIZE);
+
+    let mut text = format!(
+        "Votes of {} (page {}):\n",
+        mention_stored(db, &user)?,
+        page + 1
+    );
+    if votes.is_empty() {
+        text.push_str("<i>no votes yet</i>\n");
+    }
+
+    for vote in votes {
+        let time = Utc
+            .timestamp_opt(vote.timestamp, 0)
+            .unwrap()
+            .format("%d/%m %H:%M");
+        let line = match vote.receiver == user {
+            true => format!("{} from {}", vote.karma, mention_stored(db, &vote.giver)?),
+            false => format!("{} to {}", vote.karma, mention_stored(db, &vote.receiver)?),
+        };
+        text.push_str(&format!("{} {}", time, line));
+
+        if let Some(reason) = vote.reason {
+            text.push_str(&format!(": <i>{}</i>", html::escape(&reason)));
+        }
+
+        // links only exist for messages in supergroups
+        if let Some(url) = vote
+            .message
+            .and_then(|message| Message::url_of(vote.chat, None, message))
+        {
+            text.push_str(&format!(" <a href=\"{}\">↗</a>", url));
+        }
+        text.push('\n');
+    }
+
+    let mut buttons = vec![];
+    if page > 0 {
+        let data = Callback::History {
+            user,
+            chat,
+            page: page - 1,
+        };
+        buttons.push(InlineKeyboardButton::callback("« newer", data.encode()?));
+    }
+    if more {
+        let data = Callback::History {
+            user,
+            chat,
+            page: page + 1,
+        };
+        buttons.push(InlineKeyboardButton::callback("older »", data.encode()?));
+    }
+
+    Ok((text, InlineKeyboardMarkup::default().append_row(buttons)))
+}
@@ -0,0 +1,190 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::Utc;
+use teloxide::{
+    adaptors::DefaultParseMode,
+    requests::{Requester, ResponseResult},
+    types::{ChatId, ChatMemberUpdated, Message},
+    utils::{command::BotCommands, html},
+    ApiError, Bot, RequestError,
+};
+
+use super::group_command::GroupCommand;
+use crate::{config::Config, db::Store, metrics};
+
+// members are checked against telegram this often, for the departures the
+// bot was not told about
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+// at most this many members per second are checked, below the telegram limit
+const RECONCILE_DELAY: Duration = Duration::from_millis(100);
+
+fn welcome(db: &Store, config: &Config, chat: ChatId) -> Result<String> {
+    let triggers = &config.defaults.triggers;
+    let list = |triggers: &[String]| {
+        triggers
+            .iter()
+            .map(|trigger| format!("<code>{}</code>", html::escape(trigger)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let budget = db
+        .budgets
+        .get_or(chat.to_string(), config.defaults.budget())?;
+
+    Ok(format!(
+        "Hi! Reply to a message starting with {} to upvote its author, or with {} to downvote \
+        them. Everyone has {} upvotes and {} downvotes a day.\n\n{}",
+        list(&triggers.up),
+        list(&triggers.down),
+        budget.up,
+        budget.down,
+        GroupCommand::descriptions()
+    ))
+}
+
+async fn my_chat_member(
+    bot: DefaultParseMode<Bot>,
+    db: Arc<Store>,
+    config: Arc<Config>,
+    update: ChatMemberUpdated,
+) -> Result<()> {
+    let chat = &update.chat;
+    if !chat.is_group() && !chat.is_supergroup() {
+        return Ok(());
+    }
+
+    match (
+        update.old_chat_member.is_present(),
+        update.new_chat_member.is_present(),
+    ) {
+        (false, true) => {
+            log::info!("Added to chat {}", chat.id);
+            db.touch_chat(chat)?;
+            db.inactive.remove(chat.id.to_string())?;
+            bot.send_message(chat.id, welcome(&db, &config, chat.id)?)
+                .await?;
+        }
+        // chats left with /leave were already purged, nothing is left to keep
+        (true, false) if !db.is_known_chat(chat.id)? => {
+            log::info!("Removed from purged chat {}", chat.id);
+        }
+        (true, false) => {
+            // the data is kept, in case the bot is added back
+            log::info!("Removed from chat {}", chat.id);
+            db.inactive
+                .insert(chat.id.to_string(), Utc::now().timestamp())?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+pub async fn my_chat_member_handler(
+    bot: DefaultParseMode<Bot>,
+    db: Arc<Store>,
+    config: Arc<Config>,
+    update: ChatMemberUpdated,
+) -> ResponseResult<()> {
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["my_chat_member"])
+        .start_timer();
+    match my_chat_member(bot, db, config, update).await {
+        Ok(_) => Ok(()),
+        Err(err) => match err.downcast::<teloxide::RequestError>() {
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["my_chat_member"])
+                    .inc();
+                Err(err)
+            }
+            Err(err) => {
+                log::error!("Generic error: {}", err);
+                Ok(())
+            }
+        },
+    }
+}
+
+async fn chat_member(db: Arc<Store>, update: ChatMemberUpdated) -> Result<()> {
+    let chat = update.chat.id;
+    let user = update.new_chat_member.user.id;
+
+    let was = update.old_chat_member.is_present();
+    let is = update.new_chat_member.is_present();
+
+    if !was && is && db.rejoin(chat, user)? {
+        log::info!("User {} rejoined chat {}", user, chat);
+    } else if was && !is && db.depart(chat, user, update.date.timestamp())? {
+        log::info!("User {} left chat {}", user, chat);
+    }
+
+    Ok(())
+}
+
+// telegram only sends these to administrators of the chat
+pub async fn chat_member_handler(db: Arc<Store>, update: ChatMemberUpdated) -> ResponseResult<()> {
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["chat_member"])
+        .start_timer();
+    if let Err(err) = chat_member(db, update).await {
+        log::error!("Generic error: {}", err);
+    }
+    Ok(())
+}
+
+// a group became a supergroup, `to` is its new id
+pub async fn migration_handler(db: Arc<Store>, msg: Message, to: ChatId) -> ResponseResult<()> {
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["migration"])
+        .start_timer();
+    log::info!("Chat {} migrated to {}", msg.chat.id, to);
+    if let Err(err) = db.move_chat(msg.chat.id, to) {
+        log::error!("Could not migrate chat {} to {}: {}", msg.chat.id, to, err);
+    }
+    Ok(())
+}
+
+// marks as departed the members that are no longer in the chats, returns how many
+async fn reconcile(bot: &DefaultParseMode<Bot>, db: &Store) -> Result<usize> {
+    let mut interval = tokio::time::interval(RECONCILE_DELAY);
+    let mut departed = 0;
+    for entry in db.members.iter() {
+        let (chat, members) = entry?;
+        if db.inactive.get(&chat)?.is_some() {
+            continue;
+        }
+
+        let chat = ChatId(chat.parse()?);
+        for user in members {
+            interval.tick().await;
+            let present = match bot.get_chat_member(chat, user).await {
+                Ok(member) => member.is_present(),
+                Err(RequestError::Api(ApiError::UserNotFound)) => false,
+                Err(err) => {
+                    // the rest of the chat would likely fail the same way
+                    log::warn!("Could not check the members of {}: {}", chat, err);
+                    break;
+                }
+            };
+
+            if !present && db.depart(chat, user, Utc::now().timestamp())? {
+                departed += 1;
+            }
+        }
+    }
+    Ok(departed)
+}
+
+pub async fn reconciler(bot: DefaultParseMode<Bot>, db: Arc<Store>) {
+    let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match reconcile(&bot, &db).await {
+            Ok(0) => {}
+            Ok(departed) => log::info!("Found {} departed members", departed),
+            Err(err) => log::error!("Could not reconcile members: {}", err),
+        }
+    }
+}
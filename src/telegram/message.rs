@@ -1,61 +1,80 @@
-use std::{collections::HashSet, fmt::Display, str::FromStr, sync::Arc};
+use std::sync::Arc;
 
 use anyhow::Result;
-use bincode::{deserialize, serialize};
 use chrono::Utc;
-use serde::{Deserialize, Serialize};
 use teloxide::{
     adaptors::DefaultParseMode,
-    payloads::{AnswerCallbackQuerySetters, SendMessageSetters},
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
     requests::{Requester, ResponseResult},
-    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message, UserId},
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, UserId},
     Bot,
 };
 
-use super::{mention_chat, mention_user};
+use super::{callback::Callback, history, mention_chat, mention_user, pinned::Pinned, stats};
 use crate::{
-    business::{self, DEFAULT_DOWN, DEFAULT_UP, GRAPH_MAX_SIZE},
-    db::{Measure, Store},
+    business::{self, BanKind, Karma},
+    config::Config,
+    db::{ChatSettings, Store, Vote},
+    events, metrics,
 };
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub enum Karma {
-    Up,
-    Down,
-}
-
-impl Display for Karma {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Karma::Up => write!(f, "+"),
-            Karma::Down => write!(f, "-"),
-        }
+// why a vote from `giver` to `receiver` is not allowed in `chat`, if it isn't
+fn blocked(db: &Store, chat: ChatId, giver: UserId, receiver: UserId) -> Result<Option<&str>> {
+    if db.is_banned(chat, giver, BanKind::Give)? {
+        return Ok(Some("you can't give votes in this chat"));
+    }
+    if db.is_banned(chat, receiver, BanKind::Receive)? {
+        return Ok(Some("this user can't receive votes in this chat"));
     }
+    Ok(None)
 }
 
-impl FromStr for Karma {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.starts_with('+') {
-            Ok(Karma::Up)
-        } else if s.starts_with('-') {
-            Ok(Karma::Down)
-        } else {
-            Err(())
-        }
-    }
+// blocked votes are only answered in the chats that asked for it
+fn notify_bans(db: &Store, chat: ChatId) -> Result<bool> {
+    let settings = db
+        .chat_settings
+        .get_or(chat.to_string(), ChatSettings::default())?;
+    Ok(settings.notify_bans)
 }
 
 async fn message_handler_internal(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
+    pinned: Arc<Pinned>,
     msg: Message,
 ) -> Result<()> {
-    if let Some(Ok(modifier)) = msg.text().map(Karma::from_str) {
+    let triggers = &config.defaults.triggers;
+    if let Some((modifier, rest)) = msg.text().and_then(|text| triggers.parse(text)) {
         if let Some(reply) = msg.reply_to_message() {
             if let (Some(giver), Some(receiver)) = (msg.from(), reply.from()) {
-                if !giver.is_bot && !receiver.is_bot && giver.id != receiver.id {
+                if receiver.is_bot {
+                    metrics::VOTES.with_label_values(&["bot"]).inc();
+                } else if giver.id == receiver.id {
+                    metrics::VOTES.with_label_values(&["self"]).inc();
+                } else if db.is_opted_out(giver.id)? || db.is_opted_out(receiver.id)? {
+                    metrics::VOTES.with_label_values(&["opted_out"]).inc();
+                } else if let Some(reason) = blocked(&db, msg.chat.id, giver.id, receiver.id)? {
+                    metrics::VOTES.with_label_values(&["banned"]).inc();
+
+                    if notify_bans(&db, msg.chat.id)? {
+                        let last_message_key = format!("{}-status", msg.chat.id);
+                        if let Some(last_message) = db.last_message.get(&last_message_key)? {
+                            bot.delete_message(msg.chat.id, last_message).await.ok();
+                        }
+
+                        let text = format!("<i>{}</i>", reason);
+                        let update_message = bot
+                            .send_message(msg.chat.id, text)
+                            .reply_to_message_id(msg.id)
+                            .await?;
+                        db.last_message
+                            .insert(&last_message_key, update_message.id)?;
+                    }
+                } else if !giver.is_bot {
+                    db.update_profile(giver)?;
+                    db.update_profile(receiver)?;
+
                     let last_karma_timestamp = db.last.get_or(giver.id.to_string(), 0)?;
 
                     if business::is_assignable_karma_expired(last_karma_timestamp) {
@@ -63,21 +82,28 @@ async fn message_handler_internal(
                         db.down.remove(giver.id.to_string())?;
                     }
 
+                    let budget = db
+                        .budgets
+                        .get_or(msg.chat.id.to_string(), config.defaults.budget())?;
                     let (db_available, default_available) = match modifier {
-                        Karma::Up => (&db.up, DEFAULT_UP),
-                        Karma::Down => (&db.down, DEFAULT_DOWN),
+                        Karma::Up => (&db.up, budget.up),
+                        Karma::Down => (&db.down, budget.down),
                     };
 
-                    let available_current =
-                        db_available.get_or(giver.id.to_string(), default_available)?;
+                    // points are shared between chats, but never above the budget of this one
+                    let available_current = db_available
+                        .get_or(giver.id.to_string(), default_available)?
+                        .min(default_available);
 
                     if available_current < 1 {
+                        metrics::VOTES.with_label_values(&["no_points"]).inc();
+
                         let keyboard_text =
                             format!("use my karma as {} for {}", modifier, receiver.full_name());
                         let keyboard = InlineKeyboardMarkup::default().append_row(vec![
                             InlineKeyboardButton::callback(
                                 keyboard_text,
-                                base64::encode(serialize(&(modifier.clone(), receiver.id))?),
+                                Callback::Vote(modifier.clone(), receiver.id).encode()?,
                             ),
                         ]);
 
@@ -107,30 +133,30 @@ async fn message_handler_internal(
 
                     let karma_current = db.karma.get_or(receiver.id.to_string(), 0)?;
 
-                    let karma = match modifier {
-                        Karma::Up => karma_current + 1,
-                        Karma::Down => karma_current - 1,
-                    };
-
-                    let mut graph = db.graph.get_or(receiver.id.to_string(), vec![])?;
-                    graph.push(Measure::new(karma));
+                    let karma = business::apply_vote(karma_current, &modifier);
 
-                    if graph.len() > GRAPH_MAX_SIZE {
-                        let diff = graph.len() - GRAPH_MAX_SIZE;
-                        db.graph
-                            .insert(receiver.id.to_string(), graph[diff..].to_vec())?;
-                    } else {
-                        db.graph.insert(receiver.id.to_string(), graph)?;
-                    }
+                    db.set_karma(receiver.id, karma)?;
+                    metrics::VOTES.with_label_values(&["applied"]).inc();
 
-                    db.karma.insert(receiver.id.to_string(), karma)?;
+                    // anything after the trigger is the reason for the vote
+                    let reason = Some(rest.trim().to_string()).filter(|reason| !reason.is_empty());
 
-                    let mut members = db.members.get_or(msg.chat.id.to_string(), HashSet::new())?;
+                    let vote = Vote {
+                        chat: msg.chat.id,
+                        giver: giver.id,
+                        receiver: receiver.id,
+                        karma: modifier.clone(),
+                        timestamp,
+                        message: Some(msg.id),
+                        reason,
+                    };
+                    events::vote_applied(&db, &vote, karma_current, karma)?;
+                    db.record_vote(vote)?;
 
-                    members.insert(giver.id);
-                    members.insert(receiver.id);
+                    db.add_member(msg.chat.id, giver.id)?;
+                    db.add_member(msg.chat.id, receiver.id)?;
 
-                    db.members.insert(msg.chat.id.to_string(), members)?;
+                    pinned.touch(&db, receiver.id)?;
 
                     let last_message_key = format!("{}-{}", msg.chat.id, receiver.id);
                     if let Some(last_message) = db.last_message.get(&last_message_key)? {
@@ -153,12 +179,22 @@ async fn message_handler_internal(
 pub async fn message_handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
+    pinned: Arc<Pinned>,
     msg: Message,
 ) -> ResponseResult<()> {
-    match message_handler_internal(bot, db, msg).await {
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["message"])
+        .start_timer();
+    match message_handler_internal(bot, db, config, pinned, msg).await {
         Ok(_) => Ok(()),
         Err(err) => match err.downcast::<teloxide::RequestError>() {
-            Ok(err) => Err(err),
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["message"])
+                    .inc();
+                Err(err)
+            }
             Err(err) => {
                 log::error!("Generic error: {}", err);
                 Ok(())
@@ -167,84 +203,195 @@ pub async fn message_handler(
     }
 }
 
-async fn callback_handler_internal(
-    bot: DefaultParseMode<Bot>,
-    db: Arc<Store>,
+async fn vote(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    config: &Config,
+    pinned: &Pinned,
     cq: CallbackQuery,
+    modifier: Karma,
+    receiver_id: UserId,
 ) -> Result<()> {
-    if let Some(data) = cq.data {
-        let giver = cq.from;
-        let (modifier, receiver_id): (Karma, UserId) = deserialize(&base64::decode(data).unwrap())?;
+    let giver = cq.from;
+    let chat = match &cq.message {
+        Some(msg) => msg.chat.id,
+        None => return Ok(()),
+    };
+
+    if db.is_opted_out(giver.id)? || db.is_opted_out(receiver_id)? {
+        metrics::VOTES.with_label_values(&["opted_out"]).inc();
+        bot.answer_callback_query(cq.id)
+            .text("opted out of karma")
+            .await?;
+        return Ok(());
+    }
 
-        let karma_receiver_current = db.karma.get_or(receiver_id.to_string(), 0)?;
+    if let Some(reason) = blocked(db, chat, giver.id, receiver_id)? {
+        metrics::VOTES.with_label_values(&["banned"]).inc();
+        let mut answer = bot.answer_callback_query(cq.id);
+        if notify_bans(db, chat)? {
+            answer = answer.text(reason);
+        }
+        answer.await?;
+        return Ok(());
+    }
 
-        let karma_receiver = match modifier {
-            Karma::Up => karma_receiver_current + 1,
-            Karma::Down => karma_receiver_current - 1,
-        };
+    db.update_profile(&giver)?;
 
-        let last_karma_timestamp = db.last.get_or(giver.id.to_string(), 0)?;
+    let karma_receiver_current = db.karma.get_or(receiver_id.to_string(), 0)?;
 
-        if business::is_assignable_karma_expired(last_karma_timestamp) {
-            db.up.remove(giver.id.to_string())?;
-            db.down.remove(giver.id.to_string())?;
-        }
+    let karma_receiver = business::apply_vote(karma_receiver_current, &modifier);
 
-        let (db_available, default_available) = match modifier {
-            Karma::Up => (&db.up, DEFAULT_UP),
-            Karma::Down => (&db.down, DEFAULT_DOWN),
-        };
-
-        let available_current = db_available.get_or(giver.id.to_string(), default_available)?;
-        if available_current < 1 {
-            let karma_giver_current = db.karma.get_or(giver.id.to_string(), 0)?;
-            if karma_giver_current < 1 {
-                bot.answer_callback_query(cq.id)
-                    .text("not enough karma")
-                    .await?;
-                return Ok(());
-            }
+    let last_karma_timestamp = db.last.get_or(giver.id.to_string(), 0)?;
+
+    if business::is_assignable_karma_expired(last_karma_timestamp) {
+        db.up.remove(giver.id.to_string())?;
+        db.down.remove(giver.id.to_string())?;
+    }
 
-            let karma_giver = karma_giver_current - 1;
-            db.karma.insert(giver.id.to_string(), karma_giver)?;
-        } else {
-            let available = available_current - 1;
-            db_available.insert(giver.id.to_string(), available)?;
+    let budget = db
+        .budgets
+        .get_or(chat.to_string(), config.defaults.budget())?;
+    let (db_available, default_available) = match modifier {
+        Karma::Up => (&db.up, budget.up),
+        Karma::Down => (&db.down, budget.down),
+    };
+
+    let available_current = db_available
+        .get_or(giver.id.to_string(), default_available)?
+        .min(default_available);
+    if available_current < 1 {
+        let karma_giver_current = db.karma.get_or(giver.id.to_string(), 0)?;
+        if karma_giver_current < 1 {
+            metrics::VOTES
+                .with_label_values(&["not_enough_karma"])
+                .inc();
+            bot.answer_callback_query(cq.id)
+                .text("not enough karma")
+                .await?;
+            return Ok(());
         }
 
-        let karma_giver = db.karma.get_or(giver.id.to_string(), 0)?;
+        let karma_giver = karma_giver_current - 1;
+        db.set_karma(giver.id, karma_giver)?;
+        pinned.touch(db, giver.id)?;
+    } else {
+        let available = available_current - 1;
+        db_available.insert(giver.id.to_string(), available)?;
+    }
 
-        let source = match available_current < 1 {
-            true => "karma",
-            false => "points",
-        };
+    let karma_giver = db.karma.get_or(giver.id.to_string(), 0)?;
+
+    let source = match available_current < 1 {
+        true => "karma",
+        false => "points",
+    };
+
+    db.set_karma(receiver_id, karma_receiver)?;
+    metrics::VOTES.with_label_values(&["applied"]).inc();
+    pinned.touch(db, receiver_id)?;
+
+    let vote = Vote {
+        chat,
+        giver: giver.id,
+        receiver: receiver_id,
+        karma: modifier.clone(),
+        timestamp: Utc::now().timestamp(),
+        message: None,
+        reason: None,
+    };
+    events::vote_applied(db, &vote, karma_receiver_current, karma_receiver)?;
+    db.record_vote(vote)?;
+
+    bot.answer_callback_query(cq.id).text("thanks!").await?;
+
+    if let Some(msg) = cq.message {
+        let last_message_key = format!("{}-{}", msg.chat.id, receiver_id);
+        if let Some(last_message) = db.last_message.get(&last_message_key)? {
+            bot.delete_message(msg.chat.id, last_message).await.ok();
+        }
 
-        db.karma.insert(receiver_id.to_string(), karma_receiver)?;
+        let receiver_chat = bot.get_chat(receiver_id).await?;
+        let receiver_mention = mention_chat(&receiver_chat);
+
+        let text = format!(
+            "{} reputation of {} ({})\n\
+            <i>thanks to {}'s {} ({})</i>",
+            modifier,
+            receiver_mention,
+            karma_receiver,
+            mention_user(&giver),
+            source,
+            karma_giver
+        );
+
+        bot.edit_message_text(msg.chat.id, msg.id, text).await?;
+        db.last_message.insert(&last_message_key, msg.id)?;
+    }
 
-        bot.answer_callback_query(cq.id).text("thanks!").await?;
+    Ok(())
+}
 
-        if let Some(msg) = cq.message {
-            let last_message_key = format!("{}-{}", msg.chat.id, receiver_id);
-            if let Some(last_message) = db.last_message.get(&last_message_key)? {
-                bot.delete_message(msg.chat.id, last_message).await.ok();
+async fn callback_handler_internal(
+    bot: DefaultParseMode<Bot>,
+    db: Arc<Store>,
+    config: Arc<Config>,
+    pinned: Arc<Pinned>,
+    cq: CallbackQuery,
+) -> Result<()> {
+    let data = match &cq.data {
+        Some(data) => Callback::decode(data)?,
+        None => return Ok(()),
+    };
+
+    let kind = match data {
+        Callback::Vote(..) => "vote",
+        Callback::History { .. } => "history",
+        Callback::Stats { .. } => "stats",
+        Callback::Forget { .. } => "forget",
+    };
+    metrics::CALLBACKS.with_label_values(&[kind]).inc();
+
+    match data {
+        Callback::Vote(modifier, receiver) => {
+            vote(&bot, &db, &config, &pinned, cq, modifier, receiver).await?;
+        }
+        Callback::History { user, chat, page } => {
+            if let Some(msg) = &cq.message {
+                let (text, keyboard) = history::page(&db, user, chat, page)?;
+                bot.edit_message_text(msg.chat.id, msg.id, text)
+                    .reply_markup(keyboard)
+                    .disable_web_page_preview(true)
+                    .await?;
             }
-
-            let receiver_chat = bot.get_chat(receiver_id).await?;
-            let receiver_mention = mention_chat(&receiver_chat);
-
-            let text = format!(
-                "{} reputation of {} ({})\n\
-                <i>thanks to {}'s {} ({})</i>",
-                modifier,
-                receiver_mention,
-                karma_receiver,
-                mention_user(&giver),
-                source,
-                karma_giver
-            );
-
-            bot.edit_message_text(msg.chat.id, msg.id, text).await?;
-            db.last_message.insert(&last_message_key, msg.id)?;
+            bot.answer_callback_query(cq.id).await?;
+        }
+        Callback::Stats { chat } => {
+            if let Some(msg) = &cq.message {
+                let (text, keyboard) = match chat {
+                    Some(chat) => stats::chat(&bot, &db, cq.from.id, chat).await?,
+                    None => stats::overview(&bot, &db, &config, cq.from.id).await?,
+                };
+                bot.edit_message_text(msg.chat.id, msg.id, text)
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+            bot.answer_callback_query(cq.id).await?;
+        }
+        Callback::Forget { confirm } => {
+            if let Some(msg) = &cq.message {
+                let text = match confirm {
+                    true => {
+                        // leaderboards must be updated before the memberships are gone
+                        pinned.touch(&db, cq.from.id)?;
+                        db.forget_user(cq.from.id)?;
+                        "Your data was deleted. Use /optout to stop me from recording you again."
+                    }
+                    false => "Nothing was deleted.",
+                };
+                bot.edit_message_text(msg.chat.id, msg.id, text).await?;
+            }
+            bot.answer_callback_query(cq.id).await?;
         }
     }
 
@@ -254,12 +401,22 @@ async fn callback_handler_internal(
 pub async fn callback_handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
+    pinned: Arc<Pinned>,
     cq: CallbackQuery,
 ) -> ResponseResult<()> {
-    match callback_handler_internal(bot, db, cq).await {
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["callback"])
+        .start_timer();
+    match callback_handler_internal(bot, db, config, pinned, cq).await {
         Ok(_) => Ok(()),
         Err(err) => match err.downcast::<teloxide::RequestError>() {
-            Ok(err) => Err(err),
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["callback"])
+                    .inc();
+                Err(err)
+            }
             Err(err) => {
                 log::error!("Generic error: {}", err);
                 Ok(())
@@ -1,31 +1,95 @@
-use teloxide::types::{Chat, User, UserId};
+use anyhow::Result;
+use teloxide::{
+    adaptors::DefaultParseMode,
+    requests::Requester,
+    types::{Chat, User, UserId},
+    utils::html,
+    Bot,
+};
 
+use crate::db::{Profile, Store, FORGOTTEN};
+
+pub mod admin_command;
+pub mod callback;
 pub mod group_command;
+pub mod history;
+pub mod lifecycle;
 pub mod message;
+pub mod pinned;
+pub mod roles;
 pub mod root_command;
+pub mod stats;
 pub mod user_command;
 
 const PRIVACY_NAME: &str = "??? (Privacy settings)";
+const FORGOTTEN_NAME: &str = "<i>deleted user</i>";
 
 pub(crate) fn mention_chat(chat: &Chat) -> String {
     let receiver_name = chat
         .username()
         .map(|username| format!("@{}", username))
         .unwrap_or_else(|| chat.first_name().unwrap_or(PRIVACY_NAME).to_string());
-    format!("<a href=\"tg://user?id={}\">{}</a>", chat.id, receiver_name)
+    format!(
+        "<a href=\"tg://user?id={}\">{}</a>",
+        chat.id,
+        html::escape(&receiver_name)
+    )
 }
 
 pub(crate) fn mention_id(id: &UserId) -> String {
     format!("<a href=\"tg://user?id={}\">{}</a>", id, PRIVACY_NAME)
 }
 
+pub(crate) fn mention_profile(id: &UserId, profile: &Profile) -> String {
+    format!(
+        "<a href=\"tg://user?id={}\">{}</a>",
+        id,
+        html::escape(&profile.to_string())
+    )
+}
+
+// mention of a user known only by id, using the last seen profile if any
+pub(crate) fn mention_stored(db: &Store, id: &UserId) -> Result<String> {
+    if *id == FORGOTTEN {
+        return Ok(FORGOTTEN_NAME.to_string());
+    }
+
+    Ok(match db.profiles.get(id.to_string())? {
+        Some(profile) => mention_profile(id, &profile),
+        None => mention_id(id),
+    })
+}
+
 pub(crate) fn mention_user(user: &User) -> String {
+    let name = user
+        .username
+        .clone()
+        .map(|username| format!("@{}", username))
+        .unwrap_or_else(|| user.full_name());
     format!(
         "<a href=\"tg://user?id={}\">{}</a>",
         user.id,
-        user.username
-            .clone()
-            .map(|username| format!("@{}", username))
-            .unwrap_or_else(|| user.full_name())
+        html::escape(&name)
     )
 }
+
+// plain text name of a user, for places where html mentions can't be used
+pub(crate) async fn display_name(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    id: UserId,
+) -> Result<String> {
+    if let Some(profile) = db.profiles.get(id.to_string())? {
+        return Ok(profile.to_string());
+    }
+
+    let name = match bot.get_chat(id).await {
+        Ok(chat) => chat
+            .username()
+            .map(|username| format!("@{}", username))
+            .or_else(|| chat.first_name().map(str::to_string)),
+        Err(_) => None,
+    };
+
+    Ok(name.unwrap_or_else(|| PRIVACY_NAME.to_string()))
+}
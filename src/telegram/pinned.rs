@@ -0,0 +1,69 @@
+use std::{
+    collections::HashSet,
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use teloxide::{
+    adaptors::DefaultParseMode,
+    requests::Requester,
+    types::{ChatId, UserId},
+    Bot,
+};
+
+use super::group_command::leaderboard_text;
+use crate::db::Store;
+
+// pinned leaderboards are edited at most once per interval, no matter how
+// many votes are cast in the meantime
+const UPDATE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct Pinned {
+    dirty: Mutex<HashSet<ChatId>>,
+}
+
+impl Pinned {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // marks the pinned leaderboards showing the karma of `user` as outdated
+    pub fn touch(&self, db: &Store, user: UserId) -> Result<()> {
+        let memberships = db.memberships.get_or(user.to_string(), HashSet::new())?;
+        let mut dirty = self.dirty.lock().unwrap();
+        for chat in memberships {
+            if db.pinned.get(chat.to_string())?.is_some() {
+                dirty.insert(chat);
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&self) -> HashSet<ChatId> {
+        mem::take(&mut *self.dirty.lock().unwrap())
+    }
+}
+
+async fn update(bot: &DefaultParseMode<Bot>, db: &Store, chat: ChatId) -> Result<()> {
+    if let Some(message) = db.pinned.get(chat.to_string())? {
+        if let Some(text) = leaderboard_text(bot, db, chat, false).await? {
+            bot.edit_message_text(chat, message, text).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn updater(bot: DefaultParseMode<Bot>, db: Arc<Store>, pinned: Arc<Pinned>) {
+    let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        for chat in pinned.take() {
+            if let Err(err) = update(&bot, &db, chat).await {
+                log::warn!("Could not update pinned leaderboard of {}: {}", chat, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use teloxide::{
+    adaptors::DefaultParseMode,
+    requests::Requester,
+    types::{ChatId, UserId},
+    Bot,
+};
+
+use crate::config::Config;
+
+// administrators of a chat are asked to telegram again after this long
+const ADMINS_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Member,
+    // administrator of the chat the command was sent in
+    ChatAdmin,
+    // admin from the config, with every permission in every chat
+    Superadmin,
+}
+
+#[derive(Default)]
+pub struct Roles {
+    admins: Mutex<HashMap<ChatId, (Instant, HashSet<UserId>)>>,
+}
+
+impl Roles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn of(
+        &self,
+        bot: &DefaultParseMode<Bot>,
+        config: &Config,
+        chat: ChatId,
+        user: UserId,
+    ) -> Result<Role> {
+        if config.admins.contains(&user) {
+            return Ok(Role::Superadmin);
+        }
+
+        // private chats have no administrators
+        if chat.is_user() {
+            return Ok(Role::Member);
+        }
+
+        match self.admins(bot, chat).await?.contains(&user) {
+            true => Ok(Role::ChatAdmin),
+            false => Ok(Role::Member),
+        }
+    }
+
+    async fn admins(&self, bot: &DefaultParseMode<Bot>, chat: ChatId) -> Result<HashSet<UserId>> {
+        if let Some((fetched, admins)) = self.admins.lock().unwrap().get(&chat) {
+            if fetched.elapsed() < ADMINS_TTL {
+                return Ok(admins.clone());
+            }
+        }
+
+        let admins = bot
+            .get_chat_administrators(chat)
+            .await?
+            .into_iter()
+            .map(|member| member.user.id)
+            .collect::<HashSet<_>>();
+
+        self.admins
+            .lock()
+            .unwrap()
+            .insert(chat, (Instant::now(), admins.clone()));
+
+        Ok(admins)
+    }
+}
@@ -1,42 +1,178 @@
-use std::sync::Arc;
+use std::{cmp::Reverse, collections::HashSet, sync::Arc, time::Duration};
 
 use anyhow::Result;
+use chrono::{TimeZone, Utc};
 use teloxide::{
     adaptors::DefaultParseMode,
     requests::{Requester, ResponseResult},
-    types::{Message, UserId},
-    utils::command::BotCommands,
+    types::{ChatId, Message, UserId},
+    utils::{command::BotCommands, html},
     Bot,
 };
 
-use crate::db::Store;
+use crate::{
+    business::CHATS_LIST_SIZE,
+    db::{Audit, Store, AUDIT_TARGET},
+    metrics,
+};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 pub enum RootCommand {
-    #[command(description = "reset expiration [admin].")]
+    #[command(description = "give back the daily points of every user [admin].")]
     ResetAll,
-    #[command(description = "reset user expiration [admin].")]
+    #[command(description = "give back the daily points of a user [admin].")]
     Reset(u64),
     #[command(description = "identify user [admin].")]
     Info,
+    #[command(description = "list the chats the bot is in [admin].")]
+    Chats,
+    #[command(description = "send a message to all chats, or only to to=id,id,... [admin].")]
+    Broadcast(String),
+    #[command(description = "leave a chat and delete its data [admin].")]
+    Leave(i64),
+}
+
+// at most this many messages per second are broadcast, below the telegram limit
+const BROADCAST_INTERVAL: Duration = Duration::from_millis(100);
+
+async fn chats(bot: &DefaultParseMode<Bot>, db: &Store, admin: UserId) -> Result<()> {
+    let mut chats = vec![];
+    for chat in db.known_chats()? {
+        let info = db.chats.get(chat.to_string())?;
+        let members = db.members.get_or(chat.to_string(), HashSet::new())?;
+        chats.push((chat, info, members.len()));
+    }
+
+    // chats without activity since the registry exists go last
+    chats.sort_by_key(|(_, info, _)| Reverse(info.as_ref().map(|info| info.last_activity)));
+
+    let mut text = format!("Chats ({}):\n", chats.len());
+    for (chat, info, members) in chats.iter().take(CHATS_LIST_SIZE) {
+        let (title, activity) = match info {
+            Some(info) => {
+                let activity = Utc
+                    .timestamp_opt(info.last_activity, 0)
+                    .unwrap()
+                    .format("%d/%m/%Y %H:%M");
+                (html::escape(&info.title), activity.to_string())
+            }
+            None => ("?".to_string(), "unknown".to_string()),
+        };
+        text.push_str(&format!(
+            "{} <code>{}</code>, {} members, last active {}",
+            title, chat, members, activity
+        ));
+        if let Some(left) = db.inactive.get(chat.to_string())? {
+            let left = Utc.timestamp_opt(left, 0).unwrap().format("%d/%m/%Y");
+            text.push_str(&format!(", <i>left {}</i>", left));
+        }
+        text.push('\n');
+    }
+    if chats.len() > CHATS_LIST_SIZE {
+        text.push_str(&format!("...and {} more\n", chats.len() - CHATS_LIST_SIZE));
+    }
+
+    bot.send_message(admin, text).await?;
+
+    Ok(())
+}
+
+// sends `text` to `chats` in the background, then reports to `admin`
+async fn broadcast(bot: DefaultParseMode<Bot>, admin: UserId, chats: Vec<ChatId>, text: String) {
+    let mut interval = tokio::time::interval(BROADCAST_INTERVAL);
+    let mut sent = 0;
+    for chat in &chats {
+        interval.tick().await;
+        match bot.send_message(*chat, &text).await {
+            Ok(_) => sent += 1,
+            Err(err) => log::warn!("Could not broadcast to {}: {}", chat, err),
+        }
+    }
+
+    let report = format!("Broadcast sent to {} of {} chats.", sent, chats.len());
+    if let Err(err) = bot.send_message(admin, report).await {
+        log::warn!("Could not report broadcast: {}", err);
+    }
 }
 
 async fn handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
-    root: UserId,
     msg: Message,
     cmd: RootCommand,
 ) -> Result<()> {
+    // replies go to the admin privately
+    let admin = match msg.from() {
+        Some(user) => user.id,
+        None => return Ok(()),
+    };
+
     match cmd {
         RootCommand::Reset(user) => {
-            db.last.remove(user.to_string())?;
-            bot.send_message(root, "Reset complete.").await?;
+            let user = UserId(user);
+            db.reset_points(user)?;
+            let action = format!("gave back the points of {}", AUDIT_TARGET);
+            db.record_audit(Audit::new(msg.chat.id, admin, Some(user), action))?;
+            bot.send_message(admin, "Reset complete.").await?;
         }
         RootCommand::ResetAll => {
+            db.up.clear()?;
+            db.down.clear()?;
             db.last.clear()?;
-            bot.send_message(root, "Reset complete.").await?;
+            let action = "gave back the points of every user".to_string();
+            db.record_audit(Audit::new(msg.chat.id, admin, None, action))?;
+            bot.send_message(admin, "Reset complete.").await?;
+        }
+        RootCommand::Chats => {
+            chats(&bot, &db, admin).await?;
+        }
+        RootCommand::Broadcast(args) => {
+            // chats are only selected explicitly, so that text starting with
+            // a number is not mistaken for a chat id
+            let args = args.trim();
+            let (chats, message) = match args.strip_prefix("to=") {
+                Some(selector) => {
+                    let (ids, text) = selector
+                        .split_once(char::is_whitespace)
+                        .unwrap_or((selector, ""));
+                    let chats = ids
+                        .split(',')
+                        .map(|id| id.trim().parse().map(ChatId))
+                        .collect::<Result<Vec<_>, _>>();
+                    match chats {
+                        Ok(chats) => (chats, text.trim()),
+                        Err(_) => (vec![], ""),
+                    }
+                }
+                None => (db.known_chats()?, args),
+            };
+
+            if message.is_empty() {
+                let text = "Usage: /broadcast [to=id,id,...] text";
+                bot.send_message(admin, text).await?;
+                return Ok(());
+            }
+
+            let action = format!("broadcast to {} chats: {}", chats.len(), message);
+            db.record_audit(Audit::new(msg.chat.id, admin, None, action))?;
+
+            let text = format!("Broadcasting to {} chats.", chats.len());
+            bot.send_message(admin, text).await?;
+            let message = message.to_string();
+            tokio::spawn(broadcast(bot.clone(), admin, chats, message));
+        }
+        RootCommand::Leave(chat) => {
+            let chat = ChatId(chat);
+            if let Err(err) = bot.leave_chat(chat).await {
+                log::warn!("Could not leave {}: {}", chat, err);
+            }
+            db.purge_chat(chat)?;
+
+            let action = format!("left the chat {}", chat);
+            db.record_audit(Audit::new(msg.chat.id, admin, None, action))?;
+            bot.send_message(admin, "Left the chat and deleted its data.")
+                .await?;
         }
         RootCommand::Info => {
             if let Some(reply) = msg.reply_to_message() {
@@ -44,10 +180,10 @@ async fn handler(
                     let text = format!(
                         "User info {}: \n\
                                 - ID: {}",
-                        user.full_name(),
+                        html::escape(&user.full_name()),
                         user.id,
                     );
-                    bot.send_message(root, text).await?;
+                    bot.send_message(admin, text).await?;
                     bot.delete_message(msg.chat.id, msg.id).await.ok();
                 }
             }
@@ -60,14 +196,24 @@ async fn handler(
 pub async fn command_handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
-    root: UserId,
     msg: Message,
     cmd: RootCommand,
 ) -> ResponseResult<()> {
-    match handler(bot, db, root, msg, cmd).await {
+    metrics::COMMANDS
+        .with_label_values(&[&metrics::command(&msg)])
+        .inc();
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["root_command"])
+        .start_timer();
+    match handler(bot, db, msg, cmd).await {
         Ok(_) => Ok(()),
         Err(err) => match err.downcast::<teloxide::RequestError>() {
-            Ok(err) => Err(err),
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["root_command"])
+                    .inc();
+                Err(err)
+            }
             Err(err) => {
                 log::error!("Generic error: {}", err);
                 Ok(())
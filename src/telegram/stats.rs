@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use chrono_tz::Tz;
+use teloxide::{
+    adaptors::DefaultParseMode,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, UserId},
+    utils::html,
+    Bot,
+};
+
+use super::{callback::Callback, mention_stored};
+use crate::{
+    business::{self, Tally},
+    chart,
+    config::Config,
+    db::Store,
+};
+
+async fn chat_title(bot: &DefaultParseMode<Bot>, chat: ChatId) -> String {
+    match bot.get_chat(chat).await {
+        Ok(chat) => chat.title().unwrap_or_default().to_string(),
+        Err(_) => chat.to_string(),
+    }
+}
+
+// position of `user` in `chat`, as "#rank of members"
+fn rank(db: &Store, chat: ChatId, user: UserId, karma: i64) -> Result<String> {
+    let rank = db.ranking.rank(chat, user, karma)?;
+    let members = db.members.get_or(chat.to_string(), HashSet::new())?;
+    Ok(format!("#{} of {}", rank, members.len()))
+}
+
+pub(crate) async fn overview(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    config: &Config,
+    user: UserId,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    let current_last = db.last.get_or(user.to_string(), 0)?;
+    let expired = business::is_assignable_karma_expired(current_last);
+
+    let karma = db.karma.get_or(user.to_string(), 0)?;
+
+    let (up, down) = match expired {
+        true => (config.defaults.up, config.defaults.down),
+        false => (
+            db.up.get_or(user.to_string(), config.defaults.up)?,
+            db.down.get_or(user.to_string(), config.defaults.down)?,
+        ),
+    };
+
+    let tz: Tz = db
+        .timezones
+        .get_or(user.to_string(), config.defaults.timezone.clone())?
+        .parse()
+        .unwrap_or(Tz::UTC);
+    let reset = business::next_reset();
+    let remaining = reset - Utc::now();
+
+    let mut text = format!(
+        "Your stats: \n\
+        - {} karma\n\
+        - {} + available today\n\
+        - {} - available today\n\
+        - reset in {}h {}m, at {} {}",
+        karma,
+        up,
+        down,
+        remaining.num_hours(),
+        remaining.num_minutes() % 60,
+        reset.with_timezone(&tz).format("%H:%M"),
+        tz.name()
+    );
+
+    let week = (Utc::now() - Duration::weeks(1)).timestamp();
+    if let Some(trend) = chart::sparkline(db.history.since(user, week)?, week) {
+        text.push_str(&format!("\n- {} this week", trend));
+    }
+
+    let votes = db.votes.of(user, None, 0, usize::MAX)?;
+    let tally = Tally::new(&votes, user);
+    text.push_str(&format!(
+        "\n- {} +, {} - received in total\n\
+        - {} +, {} - given in total",
+        tally.received_up, tally.received_down, tally.given_up, tally.given_down
+    ));
+
+    let mut chats = db
+        .memberships
+        .get_or(user.to_string(), HashSet::new())?
+        .into_iter()
+        .collect::<Vec<_>>();
+    chats.sort_by_key(|chat| chat.0);
+
+    let today = (reset - Duration::days(1)).timestamp();
+    let mut buttons = vec![];
+    if !chats.is_empty() {
+        text.push_str("\n\nYour groups:");
+    }
+    for chat in chats {
+        let title = chat_title(bot, chat).await;
+        let used = votes
+            .iter()
+            .filter(|vote| vote.chat == chat && vote.giver == user && vote.timestamp >= today)
+            .count();
+        text.push_str(&format!(
+            "\n- {}: {}, {} votes given today",
+            html::escape(&title),
+            rank(db, chat, user, karma)?,
+            used
+        ));
+
+        let data = Callback::Stats { chat: Some(chat) };
+        buttons.push(vec![InlineKeyboardButton::callback(title, data.encode()?)]);
+    }
+
+    Ok((text, InlineKeyboardMarkup::new(buttons)))
+}
+
+pub(crate) async fn chat(
+    bot: &DefaultParseMode<Bot>,
+    db: &Store,
+    user: UserId,
+    chat: ChatId,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    let karma = db.karma.get_or(user.to_string(), 0)?;
+    let mut text = format!(
+        "Your stats in {}: \n\
+        - {} with {} karma",
+        html::escape(&chat_title(bot, chat).await),
+        rank(db, chat, user, karma)?,
+        karma
+    );
+
+    let week = (Utc::now() - Duration::weeks(1)).timestamp();
+    let tally = Tally::new(&db.votes.since(chat, week)?, user);
+    text.push_str(&format!(
+        "\n- {} +, {} - received this week\n\
+        - {} +, {} - given this week",
+        tally.received_up, tally.received_down, tally.given_up, tally.given_down
+    ));
+
+    let givers = business::top_givers(&db.votes.since(chat, i64::MIN)?, user);
+    if !givers.is_empty() {
+        let mut mentions = vec![];
+        for (id, net) in givers {
+            mentions.push(format!("{} ({:+})", mention_stored(db, &id)?, net));
+        }
+        text.push_str(&format!("\n- top givers: {}", mentions.join(", ")));
+    }
+
+    let data = Callback::Stats { chat: None };
+    let keyboard =
+        InlineKeyboardMarkup::default().append_row(vec![InlineKeyboardButton::callback(
+            "« back",
+            data.encode()?,
+        )]);
+
+    Ok((text, keyboard))
+}
@@ -1,18 +1,19 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
 
 use anyhow::Result;
+use chrono::Utc;
+use chrono_tz::Tz;
 use teloxide::{
     adaptors::DefaultParseMode,
+    payloads::SendMessageSetters,
     requests::{Requester, ResponseResult},
-    types::Message,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, Message},
     utils::command::BotCommands,
     Bot,
 };
 
-use crate::{
-    business::{self, DEFAULT_DOWN, DEFAULT_UP},
-    db::Store,
-};
+use super::{callback::Callback, history, stats};
+use crate::{config::Config, db::Store, metrics};
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
@@ -21,37 +22,98 @@ pub enum UserCommand {
     Start,
     #[command(description = "see your stats.")]
     Stats,
+    #[command(description = "see or set your timezone, like Europe/Rome.")]
+    Timezone(String),
+    #[command(description = "see the last votes you gave and received.")]
+    History,
+    #[command(rename = "forgetme", description = "delete all your data.")]
+    Forget,
+    #[command(description = "stop the bot from recording you.")]
+    OptOut,
+    #[command(description = "let the bot record you again.")]
+    OptIn,
 }
 
 async fn handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
     msg: Message,
     cmd: UserCommand,
 ) -> Result<()> {
     match cmd {
         UserCommand::Start | UserCommand::Stats => {
             if let Some(sender) = msg.from() {
-                let current_last = db.last.get_or(sender.id.to_string(), 0)?;
-                let expired = business::is_assignable_karma_expired(current_last);
-
-                let karma = db.karma.get_or(sender.id.to_string(), 0)?;
-
-                let (up, down) = match expired {
-                    true => (DEFAULT_UP, DEFAULT_DOWN),
-                    false => (
-                        db.up.get_or(sender.id.to_string(), DEFAULT_UP)?,
-                        db.down.get_or(sender.id.to_string(), DEFAULT_DOWN)?,
-                    ),
+                let (text, keyboard) = stats::overview(&bot, &db, &config, sender.id).await?;
+                bot.send_message(msg.chat.id, text)
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+        }
+        UserCommand::Timezone(timezone) => {
+            if let Some(sender) = msg.from() {
+                let text = match timezone.trim() {
+                    "" => {
+                        let timezone = db
+                            .timezones
+                            .get_or(sender.id.to_string(), config.defaults.timezone.clone())?;
+                        format!("Your timezone is {}.", timezone)
+                    }
+                    timezone => match Tz::from_str(timezone) {
+                        Ok(tz) => {
+                            db.timezones
+                                .insert(sender.id.to_string(), tz.name().to_string())?;
+                            format!("Your timezone is now {}.", tz.name())
+                        }
+                        Err(_) => {
+                            "<i>unknown timezone, use a name like Europe/Rome.</i>".to_string()
+                        }
+                    },
                 };
-
-                let text = format!(
-                    "Your stats: \n\
-                    - {} karma\n\
-                    - {} + available today\n\
-                    - {} - available today",
-                    karma, up, down
-                );
+                bot.send_message(msg.chat.id, text).await?;
+            }
+        }
+        UserCommand::History => {
+            if let Some(sender) = msg.from() {
+                db.update_profile(sender)?;
+                let (text, keyboard) = history::page(&db, sender.id, None, 0)?;
+                bot.send_message(msg.chat.id, text)
+                    .reply_markup(keyboard)
+                    .disable_web_page_preview(true)
+                    .await?;
+            }
+        }
+        UserCommand::Forget => {
+            let text = "This deletes your karma, karma history, profile and settings in \
+                every group. Votes you gave or received stay in the history of the other \
+                users, without your name or your reasons. This can't be undone.";
+            let keyboard = InlineKeyboardMarkup::default().append_row(vec![
+                InlineKeyboardButton::callback(
+                    "delete my data",
+                    Callback::Forget { confirm: true }.encode()?,
+                ),
+                InlineKeyboardButton::callback(
+                    "cancel",
+                    Callback::Forget { confirm: false }.encode()?,
+                ),
+            ]);
+            bot.send_message(msg.chat.id, text)
+                .reply_markup(keyboard)
+                .await?;
+        }
+        UserCommand::OptOut => {
+            if let Some(sender) = msg.from() {
+                db.optouts
+                    .insert(sender.id.to_string(), Utc::now().timestamp())?;
+                let text = "I no longer record you: you can't give or receive votes. \
+                    Use /forgetme to delete what I already know, or /optin to undo.";
+                bot.send_message(msg.chat.id, text).await?;
+            }
+        }
+        UserCommand::OptIn => {
+            if let Some(sender) = msg.from() {
+                db.optouts.remove(sender.id.to_string())?;
+                let text = "You can give and receive votes again.";
                 bot.send_message(msg.chat.id, text).await?;
             }
         }
@@ -63,13 +125,25 @@ async fn handler(
 pub async fn command_handler(
     bot: DefaultParseMode<Bot>,
     db: Arc<Store>,
+    config: Arc<Config>,
     msg: Message,
     cmd: UserCommand,
 ) -> ResponseResult<()> {
-    match handler(bot, db, msg, cmd).await {
+    metrics::COMMANDS
+        .with_label_values(&[&metrics::command(&msg)])
+        .inc();
+    let _timer = metrics::HANDLER_DURATION
+        .with_label_values(&["user_command"])
+        .start_timer();
+    match handler(bot, db, config, msg, cmd).await {
         Ok(_) => Ok(()),
         Err(err) => match err.downcast::<teloxide::RequestError>() {
-            Ok(err) => Err(err),
+            Ok(err) => {
+                metrics::TELEGRAM_ERRORS
+                    .with_label_values(&["user_command"])
+                    .inc();
+                Err(err)
+            }
             Err(err) => {
                 log::error!("Generic error: {}", err);
                 Ok(())
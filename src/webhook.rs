@@ -0,0 +1,133 @@
+use std::{convert::Infallible, net::SocketAddr, path::PathBuf, time::Duration};
+
+use anyhow::{bail, Result};
+use axum_server::{tls_openssl::OpenSSLConfig, Handle};
+use serde::Deserialize;
+use teloxide::{
+    adaptors::DefaultParseMode,
+    dispatching::update_listeners::{
+        webhooks::{self, Options},
+        UpdateListener,
+    },
+    payloads::SetWebhookSetters,
+    requests::Requester,
+    types::{AllowedUpdate, InputFile},
+    Bot,
+};
+use url::Url;
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:8443";
+// must list every kind of update the dispatcher handles, polling infers it
+const ALLOWED_UPDATES: [AllowedUpdate; 4] = [
+    AllowedUpdate::Message,
+    AllowedUpdate::CallbackQuery,
+    AllowedUpdate::MyChatMember,
+    AllowedUpdate::ChatMember,
+];
+// connections still open after this long are dropped on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub url: Url,
+    #[serde(default = "default_address")]
+    pub address: SocketAddr,
+    pub secret: Option<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+fn default_address() -> SocketAddr {
+    DEFAULT_ADDRESS.parse().unwrap()
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            address: default_address(),
+            secret: None,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !path.exists() {
+                        bail!("webhook tls file {} does not exist", path.display());
+                    }
+                }
+            }
+            (None, None) => {}
+            _ => bail!("webhook tls needs both a certificate and a key"),
+        }
+        Ok(())
+    }
+}
+
+// registers the webhook and serves it until the dispatcher stops
+pub async fn listener(
+    bot: &DefaultParseMode<Bot>,
+    webhook: &Webhook,
+) -> Result<impl UpdateListener<Err = Infallible>> {
+    let mut options = Options::new(webhook.address, webhook.url.clone());
+    if let Some(secret) = webhook.secret.clone() {
+        options = options.secret_token(secret);
+    }
+    let secret = options.get_or_gen_secret_token().to_string();
+
+    // fail before telegram starts sending updates to a server that can't start
+    let tls = match (&webhook.tls_cert, &webhook.tls_key) {
+        (Some(cert), Some(key)) => Some(OpenSSLConfig::from_pem_file(cert, key)?),
+        _ => None,
+    };
+
+    let mut request = bot
+        .inner()
+        .set_webhook(options.url.clone())
+        .secret_token(secret)
+        .allowed_updates(ALLOWED_UPDATES);
+    // a self-signed certificate must be uploaded for telegram to trust it
+    if let Some(cert) = &webhook.tls_cert {
+        request = request.certificate(InputFile::file(cert));
+    }
+    request.await?;
+
+    let (listener, stop, router) = webhooks::axum_no_setup(options);
+    let service = router.into_make_service();
+
+    let handle = Handle::new();
+    let shutdown = handle.clone();
+    tokio::spawn(async move {
+        stop.await;
+        shutdown.graceful_shutdown(Some(SHUTDOWN_GRACE));
+    });
+
+    let address = webhook.address;
+    match tls {
+        Some(config) => {
+            let server = axum_server::bind_openssl(address, config).handle(handle);
+            tokio::spawn(async move {
+                if let Err(err) = server.serve(service).await {
+                    log::error!("Webhook server error: {}", err);
+                }
+            });
+        }
+        None => {
+            let server = axum_server::bind(address).handle(handle);
+            tokio::spawn(async move {
+                if let Err(err) = server.serve(service).await {
+                    log::error!("Webhook server error: {}", err);
+                }
+            });
+        }
+    }
+
+    log::info!("Listening for webhook updates on {}", address);
+
+    Ok(listener)
+}
@@ -0,0 +1,135 @@
+use std::{net::TcpListener, sync::Arc};
+
+use axum::Extension;
+use karmacount::{
+    api,
+    config::Config,
+    db::{Profile, Store},
+};
+use reqwest::StatusCode;
+use serde_json::Value;
+use teloxide::types::{ChatId, UserId};
+
+const CHAT: ChatId = ChatId(-100);
+const OTHER_CHAT: ChatId = ChatId(-200);
+const TOKEN: &str = "chat-token";
+const OTHER_TOKEN: &str = "other-chat-token";
+
+fn populate(db: &Store) -> anyhow::Result<()> {
+    for (id, karma) in [(1, 5), (2, 20), (3, -3)] {
+        db.add_member(CHAT, UserId(id))?;
+        db.set_karma(UserId(id), karma)?;
+    }
+    // only a member of the other chat
+    db.add_member(OTHER_CHAT, UserId(4))?;
+    db.set_karma(UserId(4), 100)?;
+
+    db.profiles.insert(
+        "2",
+        Profile {
+            username: Some("bob".to_string()),
+            name: "Bob".to_string(),
+        },
+    )?;
+    db.api_tokens.insert(TOKEN, CHAT)?;
+    db.api_tokens.insert(OTHER_TOKEN, OTHER_CHAT)?;
+    Ok(())
+}
+
+// serves the api on a free port, backed by a temporary database
+fn serve() -> String {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let store = Store::new(&db).unwrap();
+    populate(&store).unwrap();
+
+    let router = api::router()
+        .layer(Extension(Arc::new(store)))
+        .layer(Extension(Arc::new(Config::default())));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service()),
+    );
+    format!("http://{}", address)
+}
+
+async fn get(url: String, token: Option<&str>) -> (StatusCode, Option<Value>) {
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status();
+    let body = response.text().await.unwrap();
+    (status, serde_json::from_str(&body).ok())
+}
+
+#[tokio::test]
+async fn requests_need_a_valid_token() {
+    let api = serve();
+    let url = format!("{}/chats/{}/leaderboard", api, CHAT);
+
+    assert_eq!(get(url.clone(), None).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        get(url.clone(), Some("bogus")).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(get(url, Some(TOKEN)).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn tokens_only_give_access_to_their_chat() {
+    let api = serve();
+
+    let other = format!("{}/chats/{}/leaderboard", api, OTHER_CHAT);
+    assert_eq!(get(other, Some(TOKEN)).await.0, StatusCode::FORBIDDEN);
+
+    // users outside the chat are indistinguishable from unknown ones
+    for user in [4, 42] {
+        for path in ["karma", "history"] {
+            let url = format!("{}/users/{}/{}", api, user, path);
+            assert_eq!(get(url, Some(TOKEN)).await.0, StatusCode::NOT_FOUND);
+        }
+    }
+
+    let url = format!("{}/users/4/karma", api);
+    assert_eq!(get(url, Some(OTHER_TOKEN)).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn leaderboard_is_sorted_by_karma() {
+    let api = serve();
+    let url = format!("{}/chats/{}/leaderboard", api, CHAT);
+
+    let (status, body) = get(url, Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::OK);
+    let members = body.unwrap();
+    let members = members.as_array().unwrap();
+
+    let fields = |field: &str| {
+        members
+            .iter()
+            .map(|member| member[field].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(fields("rank"), [1, 2, 3]);
+    assert_eq!(fields("user"), [2, 1, 3]);
+    assert_eq!(fields("karma"), [20, 5, -3]);
+    assert_eq!(members[0]["name"], "@bob");
+    assert_eq!(members[1]["name"], Value::Null);
+}
+
+#[tokio::test]
+async fn karma_has_the_rank_in_the_chat() {
+    let api = serve();
+    let url = format!("{}/users/1/karma", api);
+
+    let (status, body) = get(url, Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::OK);
+    let karma = body.unwrap();
+    assert_eq!(karma["user"], 1);
+    assert_eq!(karma["karma"], 5);
+    assert_eq!(karma["rank"], 2);
+}
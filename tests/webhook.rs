@@ -0,0 +1,139 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::OriginalUri, routing::any, Extension, Router};
+use futures::StreamExt;
+use karmacount::webhook::{self, Webhook};
+use reqwest::StatusCode;
+use teloxide::{
+    dispatching::update_listeners::{AsUpdateStream, UpdateListener},
+    requests::RequesterExt,
+    types::{ParseMode, UpdateKind},
+    Bot,
+};
+
+const SECRET: &str = "s3cret";
+const UPDATE: &str = r#"{
+    "update_id": 42,
+    "message": {
+        "message_id": 1,
+        "date": 1667000000,
+        "chat": {"id": -100, "type": "supergroup", "title": "test"},
+        "from": {"id": 10, "is_bot": false, "first_name": "alice"},
+        "text": "+1"
+    }
+}"#;
+
+type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+// answers every method like telegram would and records what was called
+fn telegram_api() -> (SocketAddr, Requests) {
+    async fn method(
+        OriginalUri(uri): OriginalUri,
+        Extension(requests): Extension<Requests>,
+        body: String,
+    ) -> &'static str {
+        requests
+            .lock()
+            .unwrap()
+            .push((uri.path().to_string(), body));
+        r#"{"ok":true,"result":true}"#
+    }
+
+    let requests = Requests::default();
+    let router = Router::new()
+        .fallback(any(method))
+        .layer(Extension(requests.clone()));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service()),
+    );
+    (address, requests)
+}
+
+// the server is started in the background, after the listener is returned
+async fn wait_for(address: SocketAddr) {
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(address).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("nothing is listening on {}", address);
+}
+
+async fn post(url: &str, secret: Option<&str>) -> StatusCode {
+    let mut request = reqwest::Client::new()
+        .post(url)
+        .header("content-type", "application/json")
+        .body(UPDATE);
+    if let Some(secret) = secret {
+        request = request.header("x-telegram-bot-api-secret-token", secret);
+    }
+    request.send().await.unwrap().status()
+}
+
+#[tokio::test]
+async fn webhook_checks_the_secret_and_forwards_updates() {
+    let (api, requests) = telegram_api();
+    let bot = Bot::new("token")
+        .set_api_url(format!("http://{}", api).parse().unwrap())
+        .parse_mode(ParseMode::Html);
+
+    let address = free_address();
+    let url = format!("http://{}/webhook", address);
+    let mut config = Webhook::new(url.parse().unwrap());
+    config.address = address;
+    config.secret = Some(SECRET.to_string());
+
+    let mut listener = webhook::listener(&bot, &config).await.unwrap();
+    wait_for(address).await;
+
+    {
+        let requests = requests.lock().unwrap();
+        let (path, body) = requests
+            .iter()
+            .find(|(path, _)| path.ends_with("/SetWebhook"))
+            .expect("the webhook was not registered");
+        assert_eq!(path, "/bottoken/SetWebhook");
+        assert!(body.contains(&url));
+        assert!(body.contains(SECRET));
+        assert!(body.contains("chat_member"));
+    }
+
+    assert_eq!(post(&url, None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(post(&url, Some("wrong")).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(post(&url, Some(SECRET)).await, StatusCode::OK);
+
+    let stop = listener.stop_token();
+    let stream = listener.as_stream();
+    futures::pin_mut!(stream);
+    let update = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("the update was not forwarded")
+        .unwrap()
+        .unwrap();
+    // the rejected updates never reach the dispatcher
+    assert_eq!(update.id, 42);
+    match update.kind {
+        UpdateKind::Message(message) => assert_eq!(message.text(), Some("+1")),
+        kind => panic!("unexpected update {:?}", kind),
+    }
+
+    stop.stop();
+    let end = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+    assert!(matches!(end, Ok(None)), "the listener did not stop");
+}